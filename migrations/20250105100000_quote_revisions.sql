@@ -0,0 +1,14 @@
+-- Keeps every version of each quote, so that updates can be undone
+CREATE TABLE IF NOT EXISTS quote_revisions (
+                                               quote_id UUID NOT NULL REFERENCES quotes (id) ON DELETE CASCADE,
+                                               version INT NOT NULL,
+                                               author TEXT NOT NULL,
+                                               quote TEXT NOT NULL,
+                                               revised_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                                               PRIMARY KEY (quote_id, version)
+);
+
+INSERT INTO quote_revisions (quote_id, version, author, quote, revised_at)
+SELECT id, version, author, quote, created_at
+FROM quotes
+ON CONFLICT DO NOTHING;
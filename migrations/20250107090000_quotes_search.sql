@@ -0,0 +1,8 @@
+-- Full-text search over quotes, with authors weighted more than the quote text
+ALTER TABLE quotes
+    ADD COLUMN IF NOT EXISTS search_vector TSVECTOR
+        GENERATED ALWAYS AS (
+            setweight(to_tsvector('english', author), 'A') || setweight(to_tsvector('english', quote), 'B')
+            ) STORED;
+
+CREATE INDEX IF NOT EXISTS quotes_search_vector_idx ON quotes USING GIN (search_vector);
@@ -0,0 +1,11 @@
+-- Finished connect games, kept so that they can be replayed later
+CREATE TABLE IF NOT EXISTS finished_games (
+                                              id UUID PRIMARY KEY,
+                                              game_id UUID,
+                                              start TEXT NOT NULL,
+                                              moves TEXT[] NOT NULL,
+                                              winner TEXT,
+                                              finished_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS finished_games_game_id_idx ON finished_games (game_id);
@@ -0,0 +1,21 @@
+-- Elo ratings of the named players of connect games, along with the results they're computed from
+CREATE TABLE IF NOT EXISTS players (
+                                       name TEXT PRIMARY KEY,
+                                       rating DOUBLE PRECISION NOT NULL,
+                                       wins INT NOT NULL DEFAULT 0,
+                                       losses INT NOT NULL DEFAULT 0,
+                                       draws INT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS game_results (
+                                            id UUID PRIMARY KEY REFERENCES finished_games (id) ON DELETE CASCADE,
+                                            cookie TEXT NOT NULL REFERENCES players (name),
+                                            milk TEXT NOT NULL REFERENCES players (name),
+                                            winner TEXT,
+                                            cookie_rating DOUBLE PRECISION NOT NULL,
+                                            milk_rating DOUBLE PRECISION NOT NULL,
+                                            played_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS game_results_cookie_idx ON game_results (cookie);
+CREATE INDEX IF NOT EXISTS game_results_milk_idx ON game_results (milk);
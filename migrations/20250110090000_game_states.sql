@@ -0,0 +1,18 @@
+-- Games in progress, snapshotted after every move so that they survive restarts and can be shared between instances
+CREATE TABLE IF NOT EXISTS game_states (
+                                           id UUID PRIMARY KEY,
+                                           start TEXT NOT NULL,
+                                           moves TEXT[] NOT NULL,
+                                           next_player TEXT NOT NULL,
+                                           cookie_token TEXT,
+                                           milk_token TEXT,
+                                           cookie_name TEXT,
+                                           milk_name TEXT,
+                                           computer_player TEXT,
+                                           computer_difficulty TEXT,
+                                           computer_depth INT,
+                                           version INT NOT NULL DEFAULT 1,
+                                           updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS game_states_updated_at_idx ON game_states (updated_at);
@@ -1,2 +1,10 @@
+pub(crate) mod ai;
+pub(crate) mod archive;
+pub(crate) mod board;
+pub(crate) mod events;
+pub(crate) mod persistence;
+pub(crate) mod ratings;
+pub(crate) mod render;
+pub(crate) mod rooms;
 pub(crate) mod routes;
 pub(crate) mod structs;
\ No newline at end of file
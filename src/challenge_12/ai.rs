@@ -0,0 +1,168 @@
+use crate::challenge_12::structs::{Grid, Move, Player, TileType};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Deserialize;
+
+/// Deepest search allowed, to keep the response times of larger boards reasonable.
+pub(crate) const MAX_SEARCH_DEPTH: u32 = 8;
+/// Slots looked at over every position of a single search. Positions of larger boards cost more, so that they get a
+/// shallower search instead of a slower one.
+const SEARCH_BUDGET: usize = 1_000_000;
+/// Score of a won position, high enough to outweigh any heuristic evaluation.
+const WIN_SCORE: i32 = 1_000_000;
+
+#[derive(Debug, Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Difficulty {
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+        }
+    }
+
+    pub fn search_depth(&self) -> u32 {
+        match self {
+            Difficulty::Easy => 2,
+            Difficulty::Medium => 4,
+            Difficulty::Hard => 6,
+        }
+    }
+
+    /// Chance of playing a random move instead of the best one.
+    fn blunder_chance(&self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.3,
+            Difficulty::Medium => 0.1,
+            Difficulty::Hard => 0.0,
+        }
+    }
+}
+
+impl TryFrom<&str> for Difficulty {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "easy" => Ok(Difficulty::Easy),
+            "medium" => Ok(Difficulty::Medium),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The server playing one of the sides of a game, with every move the rules variant of the game allows.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct ComputerPlayer {
+    pub player: Player,
+    pub difficulty: Difficulty,
+    pub depth: u32,
+}
+
+impl ComputerPlayer {
+    /// Picks the move to play, occasionally blundering depending on the difficulty.
+    pub fn choose_move(&self, grid: &Grid) -> Option<Move> {
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(self.difficulty.blunder_chance()) {
+            return grid.legal_moves(self.player).choose(&mut rng).copied();
+        }
+        best_move(grid, self.player, self.depth)
+    }
+}
+
+/// Searches the best move for the given player with alpha-beta pruned minimax, looking up to `depth` moves ahead.
+/// The search deepens one move at a time, keeping the result of the deepest one that fit in the search budget.
+/// Returns None if there's no legal move left.
+pub(crate) fn best_move(grid: &Grid, player: Player, depth: u32) -> Option<Move> {
+    let mut positions_left = SEARCH_BUDGET / grid.tiles().len();
+    let mut best = None;
+    for depth in 1..=depth.clamp(1, MAX_SEARCH_DEPTH) {
+        match search_move(grid, player, depth, &mut positions_left) {
+            Some((best_move, score)) => {
+                tracing::info!("Best move for {:?} at depth {}: {:?} (score {})", player, depth, best_move, score);
+                best = best_move;
+            }
+            None => {
+                tracing::info!("Search budget exhausted at depth {}", depth);
+                break;
+            }
+        }
+    }
+    best
+}
+
+/// Searches the best move at the given depth, returning None if the budget ran out before the end of the search.
+fn search_move(grid: &Grid, player: Player, depth: u32, positions_left: &mut usize) -> Option<(Option<Move>, i32)> {
+    let mut best = None;
+    let mut alpha = -WIN_SCORE - 1;
+    for player_move in ordered_moves(grid, player) {
+        let child = grid.with_move(player_move);
+        let score = -negamax(&child, player.opponent(), depth - 1, -WIN_SCORE - 1, -alpha, positions_left)?;
+        if best.is_none() || score > alpha {
+            alpha = score;
+            best = Some(player_move);
+        }
+    }
+    Some((best, alpha))
+}
+
+/// Scores the grid from the point of view of the player about to move, or returns None once the budget ran out.
+fn negamax(grid: &Grid, player: Player, depth: u32, mut alpha: i32, beta: i32, positions_left: &mut usize) -> Option<i32> {
+    *positions_left = positions_left.checked_sub(1)?;
+    if let Some((winner, _)) = grid.winning_line() {
+        // Quicker wins are worth more than slower ones
+        let score = WIN_SCORE + depth as i32;
+        return Some(if winner == player { score } else { -score });
+    }
+    let moves = ordered_moves(grid, player);
+    if moves.is_empty() {
+        return Some(0);
+    }
+    if depth == 0 {
+        return Some(evaluate(grid, player));
+    }
+    for player_move in moves {
+        let child = grid.with_move(player_move);
+        let score = -negamax(&child, player.opponent(), depth - 1, -beta, -alpha, positions_left)?;
+        if score >= beta {
+            return Some(score);
+        }
+        alpha = alpha.max(score);
+    }
+    Some(alpha)
+}
+
+/// Legal moves, the ones in central columns first, since they usually lead to better moves and thus to more pruning.
+fn ordered_moves(grid: &Grid, player: Player) -> Vec<Move> {
+    let center = (grid.config().cols as isize + 1) / 2;
+    let mut moves = grid.legal_moves(player);
+    moves.sort_by_key(|player_move| (grid.describe(player_move).column as isize - center).abs());
+    moves
+}
+
+/// Rewards every line that can still be completed, the more so the more tiles it already has.
+fn evaluate(grid: &Grid, player: Player) -> i32 {
+    let own = TileType::from(&player);
+    let other = TileType::from(&player.opponent());
+    grid.windows()
+        .map(|line| {
+            let own_tiles = line.iter().filter(|&&position| grid.tiles()[position] == own).count() as i32;
+            let other_tiles = line.iter().filter(|&&position| grid.tiles()[position] == other).count() as i32;
+            match (own_tiles, other_tiles) {
+                (0, 0) => 0,
+                (own_tiles, 0) => own_tiles * own_tiles,
+                (0, other_tiles) => -other_tiles * other_tiles,
+                _ => 0,
+            }
+        })
+        .sum()
+}
@@ -0,0 +1,77 @@
+use crate::challenge_12::structs::{GameState, Grid, Player};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::postgres::PgRow;
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+/// A finished game, stored as its starting position in the compact form returned by `Grid::fen`, followed by its
+/// moves in the form returned by `Grid::move_notations`. The winner is missing for draws.
+#[derive(Serialize, Debug)]
+pub(crate) struct FinishedGame {
+    id: Uuid,
+    game: Option<Uuid>,
+    start: String,
+    moves: Vec<String>,
+    winner: Option<Player>,
+    finished_at: DateTime<Utc>,
+}
+
+impl From<&PgRow> for FinishedGame {
+    fn from(row: &PgRow) -> Self {
+        Self {
+            id: row.get("id"),
+            game: row.get("game_id"),
+            start: row.get("start"),
+            moves: row.get("moves"),
+            winner: row
+                .get::<Option<&str>, _>("winner")
+                .and_then(|winner| Player::try_from(winner).ok()),
+            finished_at: row.get("finished_at"),
+        }
+    }
+}
+
+impl FinishedGame {
+    /// Plays the moves again from the starting position, returning the final board.
+    pub fn board(&self) -> Result<Grid, ()> {
+        Grid::from_notations(&self.start, &self.moves)
+    }
+}
+
+/// Stores a finished game, optionally played in a game room, returning its id.
+pub(crate) async fn archive_game(pool: &PgPool, game: Option<Uuid>, board: &Grid) -> Result<Uuid, sqlx::Error> {
+    let winner = match board.check_winner() {
+        GameState::Win(player) => Some(player.name()),
+        GameState::NoWin | GameState::Pending => None,
+    };
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO finished_games (id, game_id, start, moves, winner) VALUES ($1, $2, $3, $4, $5)")
+        .bind(id)
+        .bind(game)
+        .bind(board.start().fen())
+        .bind(board.move_notations())
+        .bind(winner)
+        .execute(pool)
+        .await?;
+    Ok(id)
+}
+
+/// Lists the finished games from the most recent one, optionally only the ones played in the given game room.
+pub(crate) async fn finished_games(pool: &PgPool, game: Option<Uuid>) -> Result<Vec<FinishedGame>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT * FROM finished_games WHERE ($1::UUID IS NULL OR game_id = $1) ORDER BY finished_at DESC",
+    )
+    .bind(game)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(FinishedGame::from).collect())
+}
+
+pub(crate) async fn finished_game(pool: &PgPool, id: &Uuid) -> Result<Option<FinishedGame>, sqlx::Error> {
+    let row = sqlx::query("SELECT * FROM finished_games WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(FinishedGame::from))
+}
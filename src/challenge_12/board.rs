@@ -0,0 +1,63 @@
+use crate::challenge_12::events::{publish, GameEvent, EVENT_CHANNEL_CAPACITY};
+use crate::challenge_12::persistence::{GameSnapshot, StoredGame};
+use crate::challenge_12::structs::{Grid, GridConfig};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex};
+
+/// Seed used for the random board generator, so that the sequence of random boards can be reproduced after a reset.
+const RANDOM_BOARD_SEED: u64 = 2024;
+
+/// The board of the original challenge, played without turns nor players. It's behind its own lock, like the game
+/// rooms, so that playing on it never blocks the other challenges.
+#[derive(Debug)]
+pub(crate) struct GlobalBoard {
+    pub board: Grid,
+    pub events: broadcast::Sender<GameEvent>,
+    rng: StdRng,
+    version: i32,
+}
+
+impl GlobalBoard {
+    pub fn new() -> Self {
+        Self {
+            board: Default::default(),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            rng: StdRng::seed_from_u64(RANDOM_BOARD_SEED),
+            version: 0,
+        }
+    }
+
+    pub fn reset(&mut self, config: GridConfig) {
+        self.board = Grid::new(config);
+        self.rng = StdRng::seed_from_u64(RANDOM_BOARD_SEED);
+    }
+
+    pub fn randomize(&mut self) {
+        self.board = Grid::random(self.board.config(), &mut self.rng);
+    }
+}
+
+/// Only the board is stored, since it's played without turns nor players.
+impl StoredGame for GlobalBoard {
+    fn snapshot(&self) -> GameSnapshot {
+        GameSnapshot::of_board(&self.board)
+    }
+
+    fn restore(&mut self, snapshot: GameSnapshot, version: i32) {
+        self.board = snapshot.board;
+        self.version = version;
+        publish(&self.events, GameEvent::Snapshot { board: self.board.to_string() });
+    }
+
+    fn version(&self) -> i32 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: i32) {
+        self.version = version;
+    }
+}
+
+pub(crate) type SharedGlobalBoard = Arc<Mutex<GlobalBoard>>;
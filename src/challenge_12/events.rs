@@ -0,0 +1,126 @@
+use crate::challenge_12::structs::{GameState, Grid, Placement};
+use async_stream::stream;
+use axum::extract::ws::{Message, WebSocket};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::Stream;
+use serde::Serialize;
+use std::convert::Infallible;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Amount of events each game keeps for its subscribers. Subscribers lagging behind skip the events they missed.
+pub(crate) const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+/// Something that happened in a game, along with the resulting rendered board.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum GameEvent {
+    /// The current state of the game, sent to new subscribers, and whenever it was changed by another instance.
+    Snapshot { board: String },
+    Place {
+        #[serde(rename = "move")]
+        last_move: Placement,
+        #[serde(flatten)]
+        state: GameState,
+        board: String,
+    },
+    Reset { board: String },
+    /// A move was taken back.
+    Undo {
+        #[serde(rename = "move")]
+        undone_move: Placement,
+        board: String,
+    },
+}
+
+impl GameEvent {
+    /// Describes the last move made on the grid, if there's one.
+    pub fn last_move(grid: &Grid, board: String) -> Option<Self> {
+        Some(GameEvent::Place {
+            last_move: grid.last_move()?,
+            state: grid.check_winner(),
+            board,
+        })
+    }
+
+    pub fn board(&self) -> &str {
+        match self {
+            GameEvent::Snapshot { board }
+            | GameEvent::Place { board, .. }
+            | GameEvent::Reset { board }
+            | GameEvent::Undo { board, .. } => board,
+        }
+    }
+}
+
+/// Publishes an event to the subscribers of a game, if there are any.
+pub(crate) fn publish(sender: &broadcast::Sender<GameEvent>, event: GameEvent) {
+    // Sending only fails when nobody is subscribed, which is fine
+    let _ = sender.send(event);
+}
+
+/// Streams every event of a game as a pair of server-sent events: a `board` event with the rendered board, followed
+/// by a `move` event with the event itself as JSON.
+pub(crate) fn sse_stream(
+    snapshot: GameEvent,
+    mut receiver: broadcast::Receiver<GameEvent>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = stream! {
+        let mut next_event = Some(snapshot);
+        loop {
+            let event = match next_event.take() {
+                Some(event) => event,
+                None => match receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::info!("Event subscriber lagged behind by {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+            };
+            yield Ok(Event::default().event("board").data(event.board()));
+            match Event::default().event("move").json_data(&event) {
+                Ok(json_event) => yield Ok(json_event),
+                Err(e) => tracing::error!("Error while serializing event: {:?}", e),
+            }
+        }
+    };
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Sends every event of a game as a JSON text message, until either side closes the socket.
+pub(crate) async fn ws_session(mut socket: WebSocket, snapshot: GameEvent, mut receiver: broadcast::Receiver<GameEvent>) {
+    let mut next_event = Some(snapshot);
+    loop {
+        let event = match next_event.take() {
+            Some(event) => event,
+            None => tokio::select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::info!("Event subscriber lagged behind by {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                message = socket.recv() => match message {
+                    // Incoming messages are ignored, the socket only pushes events
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+            },
+        };
+        let json = match serde_json::to_string(&event) {
+            Ok(json) => json,
+            Err(e) => {
+                tracing::error!("Error while serializing event: {:?}", e);
+                continue;
+            }
+        };
+        if socket.send(Message::Text(json)).await.is_err() {
+            break;
+        }
+    }
+    tracing::info!("Closing event socket");
+}
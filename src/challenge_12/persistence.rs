@@ -0,0 +1,184 @@
+use crate::challenge_12::ai::{ComputerPlayer, Difficulty};
+use crate::challenge_12::structs::{Grid, Player};
+use sqlx::postgres::PgRow;
+use sqlx::{PgConnection, PgPool, Postgres, Row, Transaction};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Id under which the global board is stored, next to the game rooms.
+pub(crate) const GLOBAL_BOARD_ID: Uuid = Uuid::nil();
+
+/// State of a game as stored in the database, where every instance can pick it up.
+#[derive(Debug)]
+pub(crate) struct GameSnapshot {
+    pub board: Grid,
+    pub next_player: Player,
+    pub tokens: HashMap<Player, String>,
+    pub names: HashMap<Player, String>,
+    pub computer: Option<ComputerPlayer>,
+}
+
+impl GameSnapshot {
+    /// Snapshot of a board played without turns nor players, such as the global one.
+    pub fn of_board(board: &Grid) -> Self {
+        Self {
+            board: board.clone(),
+            next_player: Player::Cookie,
+            tokens: HashMap::new(),
+            names: HashMap::new(),
+            computer: None,
+        }
+    }
+}
+
+impl TryFrom<&PgRow> for GameSnapshot {
+    type Error = ();
+
+    fn try_from(row: &PgRow) -> Result<Self, Self::Error> {
+        let board = Grid::from_notations(row.get("start"), &row.get::<Vec<String>, _>("moves"))?;
+        let per_player = |column: &str| {
+            [Player::Cookie, Player::Milk]
+                .into_iter()
+                .filter_map(|player| Some((player, row.get::<Option<String>, _>(format!("{}_{}", player.name(), column).as_str())?)))
+                .collect()
+        };
+        let computer = match row.get::<Option<&str>, _>("computer_player") {
+            Some(player) => Some(ComputerPlayer {
+                player: Player::try_from(player)?,
+                difficulty: Difficulty::try_from(row.get::<&str, _>("computer_difficulty"))?,
+                depth: row.get::<i32, _>("computer_depth").try_into().map_err(|_| ())?,
+            }),
+            None => None,
+        };
+        Ok(Self {
+            board,
+            next_player: Player::try_from(row.get::<&str, _>("next_player"))?,
+            tokens: per_player("token"),
+            names: per_player("name"),
+            computer,
+        })
+    }
+}
+
+/// A game whose state is stored in the database. Each copy in memory remembers the version of the stored state it
+/// reflects, so that changes made by other instances can be picked up.
+pub(crate) trait StoredGame {
+    fn snapshot(&self) -> GameSnapshot;
+    fn restore(&mut self, snapshot: GameSnapshot, version: i32);
+    fn version(&self) -> i32;
+    fn set_version(&mut self, version: i32);
+}
+
+fn parse_row(row: &PgRow) -> Result<(GameSnapshot, i32), sqlx::Error> {
+    let snapshot = GameSnapshot::try_from(row).map_err(|_| sqlx::Error::Decode("invalid stored game".into()))?;
+    Ok((snapshot, row.get("version")))
+}
+
+async fn fetch_game(conn: &mut PgConnection, id: &Uuid, for_update: bool) -> Result<Option<(GameSnapshot, i32)>, sqlx::Error> {
+    let query = if for_update {
+        "SELECT * FROM game_states WHERE id = $1 FOR UPDATE"
+    } else {
+        "SELECT * FROM game_states WHERE id = $1"
+    };
+    match sqlx::query(query).bind(id).fetch_optional(conn).await? {
+        Some(row) => parse_row(&row).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Stores the state of a game, returning its new version.
+async fn store_game(conn: &mut PgConnection, id: &Uuid, snapshot: &GameSnapshot) -> Result<i32, sqlx::Error> {
+    let row = sqlx::query(
+        "INSERT INTO game_states (id, start, moves, next_player, cookie_token, milk_token, cookie_name, milk_name,
+                                  computer_player, computer_difficulty, computer_depth)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+         ON CONFLICT (id) DO UPDATE SET start = $2, moves = $3, next_player = $4, cookie_token = $5, milk_token = $6,
+                                        cookie_name = $7, milk_name = $8, computer_player = $9,
+                                        computer_difficulty = $10, computer_depth = $11,
+                                        version = game_states.version + 1, updated_at = CURRENT_TIMESTAMP
+         RETURNING version",
+    )
+    .bind(id)
+    .bind(snapshot.board.start().fen())
+    .bind(snapshot.board.move_notations())
+    .bind(snapshot.next_player.name())
+    .bind(snapshot.tokens.get(&Player::Cookie))
+    .bind(snapshot.tokens.get(&Player::Milk))
+    .bind(snapshot.names.get(&Player::Cookie))
+    .bind(snapshot.names.get(&Player::Milk))
+    .bind(snapshot.computer.map(|computer| computer.player.name()))
+    .bind(snapshot.computer.map(|computer| computer.difficulty.name()))
+    .bind(snapshot.computer.map(|computer| computer.depth as i32))
+    .fetch_one(conn)
+    .await?;
+    Ok(row.get("version"))
+}
+
+/// Fetches a game that this instance doesn't have in memory, such as one created by another instance.
+pub(crate) async fn load_game(pool: &PgPool, id: &Uuid) -> Result<Option<(GameSnapshot, i32)>, sqlx::Error> {
+    fetch_game(&mut *pool.acquire().await?, id, false).await
+}
+
+/// Brings a game up to date with its stored state, if another instance changed it.
+pub(crate) async fn refresh<G: StoredGame>(pool: &PgPool, id: &Uuid, game: &mut G) -> Result<(), sqlx::Error> {
+    let version: Option<i32> = sqlx::query("SELECT version FROM game_states WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .map(|row| row.get("version"));
+    if version.is_some_and(|version| version != game.version()) {
+        if let Some((snapshot, version)) = load_game(pool, id).await? {
+            tracing::info!("Game {} changed elsewhere, reloading version {}", id, version);
+            game.restore(snapshot, version);
+        }
+    }
+    Ok(())
+}
+
+/// Locks the stored state of a game until the returned transaction ends, bringing the game up to date with it first.
+/// Instances sharing the database thus take turns changing a game, each one seeing the changes of the others.
+pub(crate) async fn lock<G: StoredGame>(pool: &PgPool, id: &Uuid, game: &mut G) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if let Some((snapshot, version)) = fetch_game(&mut tx, id, true).await? {
+        if version != game.version() {
+            tracing::info!("Game {} changed elsewhere, reloading version {}", id, version);
+            game.restore(snapshot, version);
+        }
+    }
+    Ok(tx)
+}
+
+/// Stores the state of a game locked with `lock`, releasing the lock.
+pub(crate) async fn save<G: StoredGame>(mut tx: Transaction<'static, Postgres>, id: &Uuid, game: &mut G) -> Result<(), sqlx::Error> {
+    let version = store_game(&mut tx, id, &game.snapshot()).await?;
+    tx.commit().await?;
+    game.set_version(version);
+    Ok(())
+}
+
+/// Lists every stored game, to restore them on startup.
+pub(crate) async fn stored_games(pool: &PgPool) -> Result<Vec<(Uuid, GameSnapshot, i32)>, sqlx::Error> {
+    let rows = sqlx::query("SELECT * FROM game_states").fetch_all(pool).await?;
+    let mut games = Vec::new();
+    for row in &rows {
+        let id: Uuid = row.get("id");
+        match parse_row(row) {
+            Ok((snapshot, version)) => games.push((id, snapshot, version)),
+            Err(e) => tracing::error!("Error while restoring game {}: {:?}", id, e),
+        }
+    }
+    Ok(games)
+}
+
+/// Deletes the stored game rooms that haven't changed for longer than the TTL, returning how many were deleted.
+pub(crate) async fn delete_idle_games(pool: &PgPool, ttl: Duration) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "DELETE FROM game_states WHERE id <> $1 AND updated_at < CURRENT_TIMESTAMP - make_interval(secs => $2)",
+    )
+    .bind(GLOBAL_BOARD_ID)
+    .bind(ttl.as_secs_f64())
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
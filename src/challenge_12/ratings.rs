@@ -0,0 +1,173 @@
+use crate::challenge_12::structs::{GameState, Player};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::postgres::PgRow;
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+/// Rating of players who haven't finished any game yet.
+const INITIAL_RATING: f64 = 1500.0;
+/// Largest change of rating a single game can cause.
+const K_FACTOR: f64 = 32.0;
+/// Amount of players shown on the leaderboard.
+const LEADERBOARD_SIZE: i64 = 100;
+
+#[derive(Serialize, Debug)]
+pub(crate) struct PlayerRating {
+    name: String,
+    rating: f64,
+    wins: i32,
+    losses: i32,
+    draws: i32,
+}
+
+impl From<&PgRow> for PlayerRating {
+    fn from(row: &PgRow) -> Self {
+        Self {
+            name: row.get("name"),
+            rating: row.get("rating"),
+            wins: row.get("wins"),
+            losses: row.get("losses"),
+            draws: row.get("draws"),
+        }
+    }
+}
+
+/// A game from the point of view of one of its players, with the rating they had right after it.
+#[derive(Serialize, Debug)]
+pub(crate) struct RatedGame {
+    game: Uuid,
+    side: String,
+    opponent: String,
+    result: String,
+    rating: f64,
+    played_at: DateTime<Utc>,
+}
+
+impl From<&PgRow> for RatedGame {
+    fn from(row: &PgRow) -> Self {
+        Self {
+            game: row.get("id"),
+            side: row.get("side"),
+            opponent: row.get("opponent"),
+            result: row.get("result"),
+            rating: row.get("rating"),
+            played_at: row.get("played_at"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct PlayerStats {
+    #[serde(flatten)]
+    player: PlayerRating,
+    history: Vec<RatedGame>,
+}
+
+/// Computes the new ratings of two players after a game, given the score of the first one: 1 for a win, 0.5 for a
+/// draw and 0 for a loss.
+fn elo(rating: f64, opponent_rating: f64, score: f64) -> (f64, f64) {
+    let expected = 1.0 / (1.0 + 10f64.powf((opponent_rating - rating) / 400.0));
+    let change = K_FACTOR * (score - expected);
+    (rating + change, opponent_rating - change)
+}
+
+/// Records the result of a finished game between two named players, updating their ratings.
+/// The players rows are locked while doing so, so that concurrent results are applied one after the other.
+pub(crate) async fn record_result(
+    pool: &PgPool,
+    id: Uuid,
+    (cookie, milk): &(String, String),
+    state: GameState,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("INSERT INTO players (name, rating) VALUES ($1, $3), ($2, $3) ON CONFLICT DO NOTHING")
+        .bind(cookie)
+        .bind(milk)
+        .bind(INITIAL_RATING)
+        .execute(&mut *tx)
+        .await?;
+    let rating_of = |name: &str| {
+        sqlx::query("SELECT rating FROM players WHERE name = $1 FOR UPDATE")
+            .bind(name.to_string())
+    };
+    // Always lock the rows in the same order to avoid deadlocks
+    let (first, second) = if cookie < milk { (cookie, milk) } else { (milk, cookie) };
+    let first_rating: f64 = rating_of(first).fetch_one(&mut *tx).await?.get("rating");
+    let second_rating: f64 = rating_of(second).fetch_one(&mut *tx).await?.get("rating");
+    let (cookie_rating, milk_rating) = if first == cookie {
+        (first_rating, second_rating)
+    } else {
+        (second_rating, first_rating)
+    };
+    let (cookie_score, winner) = match state {
+        GameState::Win(Player::Cookie) => (1.0, Some(Player::Cookie.name())),
+        GameState::Win(Player::Milk) => (0.0, Some(Player::Milk.name())),
+        GameState::NoWin | GameState::Pending => (0.5, None),
+    };
+    let (cookie_rating, milk_rating) = elo(cookie_rating, milk_rating, cookie_score);
+    for (name, rating, score) in [(cookie, cookie_rating, cookie_score), (milk, milk_rating, 1.0 - cookie_score)] {
+        sqlx::query(
+            "UPDATE players SET rating = $2,
+                 wins = wins + ($3 = 1.0)::INT, losses = losses + ($3 = 0.0)::INT, draws = draws + ($3 = 0.5)::INT
+             WHERE name = $1",
+        )
+        .bind(name)
+        .bind(rating)
+        .bind(score)
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query(
+        "INSERT INTO game_results (id, cookie, milk, winner, cookie_rating, milk_rating) VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(id)
+    .bind(cookie)
+    .bind(milk)
+    .bind(winner)
+    .bind(cookie_rating)
+    .bind(milk_rating)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+/// Lists the best rated players.
+pub(crate) async fn leaderboard(pool: &PgPool) -> Result<Vec<PlayerRating>, sqlx::Error> {
+    let rows = sqlx::query("SELECT * FROM players ORDER BY rating DESC, name LIMIT $1")
+        .bind(LEADERBOARD_SIZE)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(PlayerRating::from).collect())
+}
+
+/// Returns the rating of a player along with every game they finished, from the oldest one.
+pub(crate) async fn player_stats(pool: &PgPool, name: &str) -> Result<Option<PlayerStats>, sqlx::Error> {
+    let player = match sqlx::query("SELECT * FROM players WHERE name = $1")
+        .bind(name)
+        .fetch_optional(pool)
+        .await?
+    {
+        Some(row) => PlayerRating::from(&row),
+        None => return Ok(None),
+    };
+    let history = sqlx::query(
+        "SELECT id, played_at,
+             CASE WHEN cookie = $1 THEN 'cookie' ELSE 'milk' END AS side,
+             CASE WHEN cookie = $1 THEN milk ELSE cookie END AS opponent,
+             CASE WHEN winner IS NULL THEN 'draw'
+                  WHEN (winner = 'cookie') = (cookie = $1) THEN 'win'
+                  ELSE 'loss' END AS result,
+             CASE WHEN cookie = $1 THEN cookie_rating ELSE milk_rating END AS rating
+         FROM game_results
+         WHERE cookie = $1 OR milk = $1
+         ORDER BY played_at",
+    )
+    .bind(name)
+    .fetch_all(pool)
+    .await?;
+    Ok(Some(PlayerStats {
+        player,
+        history: history.iter().map(RatedGame::from).collect(),
+    }))
+}
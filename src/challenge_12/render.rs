@@ -0,0 +1,117 @@
+use crate::challenge_12::structs::{Grid, TileType};
+use resvg::{tiny_skia, usvg};
+use serde::Deserialize;
+use std::fmt::Write;
+
+/// Size in pixels of each slot of the grid, walls included.
+const CELL_SIZE: usize = 64;
+const TILE_RADIUS: usize = 26;
+
+const EMPTY_COLOR: &str = "#1f1f24";
+const WALL_COLOR: &str = "#d4d4d8";
+const WALL_EDGE_COLOR: &str = "#a1a1aa";
+const COOKIE_COLOR: &str = "#c98b47";
+const COOKIE_EDGE_COLOR: &str = "#8a5a2b";
+const CHIP_COLOR: &str = "#4a2c17";
+const MILK_COLOR: &str = "#f4f7fb";
+const MILK_EDGE_COLOR: &str = "#9bb4cc";
+const HIGHLIGHT_COLOR: &str = "#facc15";
+
+#[derive(Deserialize, Debug, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ImageFormat {
+    Svg,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ImageFormat::Svg => "image/svg+xml",
+            ImageFormat::Png => "image/png",
+        }
+    }
+}
+
+/// Draws the grid the same way it's printed: walls on both sides and below it, and the tiles in between.
+/// The winning line, if any, is circled and crossed out.
+pub(crate) fn svg(grid: &Grid) -> String {
+    let config = grid.config();
+    let (width, height) = ((config.cols + 2) * CELL_SIZE, (config.rows + 1) * CELL_SIZE);
+    let center = |position: usize| {
+        let (row, col) = (position / config.cols, position % config.cols);
+        ((col + 1) * CELL_SIZE + CELL_SIZE / 2, row * CELL_SIZE + CELL_SIZE / 2)
+    };
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+    );
+    // Writing to a String can't fail
+    let _ = write!(svg, r#"<rect width="{width}" height="{height}" fill="{EMPTY_COLOR}"/>"#);
+    let walls = (0..config.rows)
+        .flat_map(|row| [(0, row), (config.cols + 1, row)])
+        .chain((0..config.cols + 2).map(|col| (col, config.rows)));
+    for (col, row) in walls {
+        let _ = write!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{CELL_SIZE}" height="{CELL_SIZE}" fill="{WALL_COLOR}" stroke="{WALL_EDGE_COLOR}" stroke-width="2"/>"#,
+            col * CELL_SIZE,
+            row * CELL_SIZE,
+        );
+    }
+    for (position, tile) in grid.tiles().iter().enumerate() {
+        let (x, y) = center(position);
+        match tile {
+            TileType::Cookie => {
+                let _ = write!(
+                    svg,
+                    r#"<circle cx="{x}" cy="{y}" r="{TILE_RADIUS}" fill="{COOKIE_COLOR}" stroke="{COOKIE_EDGE_COLOR}" stroke-width="3"/>"#
+                );
+                for (dx, dy) in [(-9, -8), (8, -5), (-3, 9), (10, 9)] {
+                    let _ = write!(
+                        svg,
+                        r#"<circle cx="{}" cy="{}" r="4" fill="{CHIP_COLOR}"/>"#,
+                        x as isize + dx,
+                        y as isize + dy,
+                    );
+                }
+            }
+            TileType::Milk => {
+                let _ = write!(
+                    svg,
+                    r#"<circle cx="{x}" cy="{y}" r="{TILE_RADIUS}" fill="{MILK_COLOR}" stroke="{MILK_EDGE_COLOR}" stroke-width="3"/>"#
+                );
+            }
+            TileType::Empty | TileType::Wall => {}
+        }
+    }
+    if let Some((_, line)) = grid.winning_line() {
+        for &position in &line {
+            let (x, y) = center(position);
+            let _ = write!(
+                svg,
+                r#"<circle cx="{x}" cy="{y}" r="{}" fill="none" stroke="{HIGHLIGHT_COLOR}" stroke-width="4"/>"#,
+                TILE_RADIUS + 4,
+            );
+        }
+        let ((x1, y1), (x2, y2)) = (center(line[0]), center(line[line.len() - 1]));
+        let _ = write!(
+            svg,
+            r#"<line x1="{x1}" y1="{y1}" x2="{x2}" y2="{y2}" stroke="{HIGHLIGHT_COLOR}" stroke-width="8" stroke-linecap="round" opacity="0.8"/>"#
+        );
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Rasterizes the drawing of the grid.
+pub(crate) fn png(grid: &Grid) -> Result<Vec<u8>, ()> {
+    let tree = usvg::Tree::from_str(&svg(grid), &usvg::Options::default()).map_err(|e| {
+        tracing::error!("Error while parsing board drawing: {:?}", e);
+    })?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height()).ok_or(())?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap.encode_png().map_err(|e| {
+        tracing::error!("Error while encoding board image: {:?}", e);
+    })
+}
@@ -0,0 +1,384 @@
+use crate::challenge_12::ai::ComputerPlayer;
+use crate::challenge_12::events::{publish, GameEvent, EVENT_CHANNEL_CAPACITY};
+use crate::challenge_12::persistence::{delete_idle_games, GameSnapshot, StoredGame};
+use crate::challenge_12::structs::{BoardView, GameState, Grid, GridConfig, Placement, Player, TileType};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Mutex};
+use uuid::Uuid;
+
+/// Length of the tokens identifying the players who joined a room.
+const PLAYER_TOKEN_LENGTH: usize = 32;
+/// Longest name players can give when joining a room.
+const MAX_PLAYER_NAME_LENGTH: usize = 32;
+
+#[derive(Debug)]
+pub(crate) enum JoinError {
+    SeatTaken,
+    InvalidName,
+}
+
+#[derive(Debug)]
+pub(crate) enum LoadError {
+    /// The numbers of cookies and milk can't come from players taking turns, starting with cookie.
+    UnbalancedTiles,
+}
+
+#[derive(Debug)]
+pub(crate) enum UndoError {
+    /// No move was played since the start of the game.
+    NoMove,
+    /// The token doesn't belong to the player who made the last move.
+    Unauthorized,
+}
+
+#[derive(Debug)]
+pub(crate) enum MoveError {
+    /// The token is missing or doesn't belong to the player making the move.
+    Unauthorized,
+    OutOfTurn,
+}
+
+/// A game played independently of the others, each one behind its own lock.
+/// Players must join a side to play it, and can only move in turn, starting with cookie.
+/// One of the sides can be played by the server instead.
+#[derive(Debug)]
+pub(crate) struct GameRoom {
+    pub board: Grid,
+    next_player: Player,
+    tokens: HashMap<Player, String>,
+    names: HashMap<Player, String>,
+    computer: Option<ComputerPlayer>,
+    events: broadcast::Sender<GameEvent>,
+    last_active: Instant,
+    version: i32, // version of the stored room this one reflects, 0 if it was never stored
+}
+
+/// Machine-readable representation of a game room, with the player to move while the game is in progress.
+#[derive(Debug, Serialize)]
+pub(crate) struct RoomView {
+    #[serde(flatten)]
+    board: BoardView,
+    next_player: Option<Player>,
+}
+
+impl Display for GameRoom {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.board)?;
+        if let GameState::Pending = self.board.check_winner() {
+            writeln!(f, "{} to play.", TileType::from(&self.next_player))?;
+        }
+        Ok(())
+    }
+}
+
+impl GameRoom {
+    fn new(config: GridConfig, computer: Option<ComputerPlayer>) -> Self {
+        Self {
+            board: Grid::new(config),
+            next_player: Player::Cookie,
+            tokens: HashMap::new(),
+            names: HashMap::new(),
+            computer,
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            last_active: Instant::now(),
+            version: 0,
+        }
+    }
+
+    pub fn from_snapshot(snapshot: GameSnapshot, version: i32) -> Self {
+        let mut room = Self::new(snapshot.board.config(), snapshot.computer);
+        room.restore(snapshot, version);
+        room
+    }
+
+    pub fn subscribe(&self) -> (GameEvent, broadcast::Receiver<GameEvent>) {
+        (GameEvent::Snapshot { board: self.to_string() }, self.events.subscribe())
+    }
+
+    /// Lets the subscribers know about the last move.
+    pub fn publish_move(&self) {
+        if let Some(event) = GameEvent::last_move(&self.board, self.to_string()) {
+            publish(&self.events, event);
+        }
+    }
+
+    pub fn view(&self) -> RoomView {
+        let board = self.board.view();
+        let next_player = match self.board.check_winner() {
+            GameState::Pending => Some(self.next_player),
+            GameState::Win(_) | GameState::NoWin => None,
+        };
+        RoomView { board, next_player }
+    }
+
+    pub fn next_player(&self) -> Player {
+        self.next_player
+    }
+
+    pub fn computer(&self) -> Option<ComputerPlayer> {
+        self.computer
+    }
+
+    /// Returns the computer player if it's its turn to move in a game still in progress.
+    pub fn computer_to_move(&self) -> Option<ComputerPlayer> {
+        self.computer
+            .filter(|computer| computer.player == self.next_player)
+            .filter(|_| matches!(self.board.check_winner(), GameState::Pending))
+    }
+
+    /// Starts a new game with the given configuration. Players keep their sides.
+    pub fn reset(&mut self, config: GridConfig) {
+        self.board = Grid::new(config);
+        self.next_player = Player::Cookie;
+        publish(&self.events, GameEvent::Reset { board: self.to_string() });
+    }
+
+    /// Replaces the board with the given position. The player to move is deduced from the number of tiles of each
+    /// player, since cookie always starts.
+    pub fn load(&mut self, board: Grid) -> Result<(), LoadError> {
+        let count = |tile| board.tiles().iter().filter(|&&t| t == tile).count();
+        let (cookies, milk) = (count(TileType::Cookie), count(TileType::Milk));
+        self.next_player = if cookies == milk {
+            Player::Cookie
+        } else if cookies == milk + 1 {
+            Player::Milk
+        } else {
+            return Err(LoadError::UnbalancedTiles);
+        };
+        self.board = board;
+        publish(&self.events, GameEvent::Reset { board: self.to_string() });
+        Ok(())
+    }
+
+    /// Takes the given side, returning the token the player must use to move.
+    /// Players can give their name, so that the games they finish count for the leaderboard.
+    pub fn join(&mut self, player: Player, name: Option<String>) -> Result<String, JoinError> {
+        let computer_side = self.computer.is_some_and(|computer| computer.player == player);
+        if computer_side || self.tokens.contains_key(&player) {
+            return Err(JoinError::SeatTaken);
+        }
+        if let Some(name) = name {
+            let name = name.trim();
+            if name.is_empty() || name.chars().count() > MAX_PLAYER_NAME_LENGTH {
+                return Err(JoinError::InvalidName);
+            }
+            self.names.insert(player, name.to_string());
+        }
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(PLAYER_TOKEN_LENGTH)
+            .map(char::from)
+            .collect();
+        self.tokens.insert(player, token.clone());
+        Ok(token)
+    }
+
+    /// Checks whether the holder of the given token can move as the given player right now.
+    pub fn check_move(&self, player: Player, token: Option<&str>) -> Result<(), MoveError> {
+        match (self.tokens.get(&player), token) {
+            (Some(expected), Some(token)) if expected == token => {}
+            _ => return Err(MoveError::Unauthorized),
+        }
+        if player != self.next_player {
+            return Err(MoveError::OutOfTurn);
+        }
+        Ok(())
+    }
+
+    /// Returns the names of the cookie and milk players, if both sides were taken by different named players, for the
+    /// result of the game to count for their ratings. Games started from a loaded position are unrated, since either
+    /// player could have loaded one that they're about to win.
+    pub fn rated_participants(&self) -> Option<(String, String)> {
+        if self.board.start().tiles().iter().any(|&tile| tile != TileType::Empty) {
+            return None;
+        }
+        let cookie = self.names.get(&Player::Cookie)?;
+        let milk = self.names.get(&Player::Milk)?;
+        (cookie != milk).then(|| (cookie.clone(), milk.clone()))
+    }
+
+    /// Checks whether the holder of the given token plays either side of the game.
+    pub fn is_seated(&self, token: Option<&str>) -> bool {
+        token.is_some_and(|token| self.tokens.values().any(|expected| expected == token))
+    }
+
+    /// Takes back the last move, giving the turn back to whoever made it, and returns the moves taken back.
+    /// When playing against the computer, its answer is taken back as well, so that it's the human's turn again.
+    /// Only the player who made the move can take it back, so that nobody takes back the moves of their opponent.
+    pub fn undo(&mut self, token: Option<&str>) -> Result<Vec<Placement>, UndoError> {
+        let is_computer = |player: Player| self.computer.is_some_and(|computer| computer.player == player);
+        let last_mover = self
+            .board
+            .history()
+            .into_iter()
+            .rev()
+            .map(|placement| placement.player)
+            .find(|&player| !is_computer(player))
+            .ok_or(UndoError::NoMove)?;
+        match (self.tokens.get(&last_mover), token) {
+            (Some(expected), Some(token)) if expected == token => {}
+            _ => return Err(UndoError::Unauthorized),
+        }
+        let mut undone = Vec::new();
+        while let Some(placement) = self.board.undo() {
+            self.next_player = placement.player;
+            let board = self.to_string();
+            publish(&self.events, GameEvent::Undo { undone_move: placement.clone(), board });
+            undone.push(placement);
+            if !self.computer.is_some_and(|computer| computer.player == self.next_player) {
+                break;
+            }
+        }
+        Ok(undone)
+    }
+
+    /// Hands the turn over to the other player.
+    pub fn end_turn(&mut self) {
+        self.next_player = self.next_player.opponent();
+    }
+
+    /// Marks the room as in use, postponing its eviction.
+    pub fn touch(&mut self) {
+        self.last_active = Instant::now();
+    }
+}
+
+impl StoredGame for GameRoom {
+    fn snapshot(&self) -> GameSnapshot {
+        GameSnapshot {
+            board: self.board.clone(),
+            next_player: self.next_player,
+            tokens: self.tokens.clone(),
+            names: self.names.clone(),
+            computer: self.computer,
+        }
+    }
+
+    /// Replaces the state of the room with the stored one, letting the subscribers know about it.
+    fn restore(&mut self, snapshot: GameSnapshot, version: i32) {
+        self.board = snapshot.board;
+        self.next_player = snapshot.next_player;
+        self.tokens = snapshot.tokens;
+        self.names = snapshot.names;
+        self.computer = snapshot.computer;
+        self.version = version;
+        publish(&self.events, GameEvent::Snapshot { board: self.to_string() });
+    }
+
+    fn version(&self) -> i32 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: i32) {
+        self.version = version;
+    }
+}
+
+pub(crate) type SharedGameRoom = Arc<Mutex<GameRoom>>;
+
+/// Keeps track of every game room, evicting the ones left idle for longer than the configured TTL.
+/// The map is only locked for lookups, so that moves in a room never block the other rooms.
+#[derive(Debug)]
+pub(crate) struct GameRooms {
+    rooms: RwLock<HashMap<Uuid, SharedGameRoom>>,
+    ttl: Duration,
+}
+
+impl GameRooms {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            rooms: RwLock::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    pub fn create(&self, config: GridConfig, computer: Option<ComputerPlayer>) -> (Uuid, SharedGameRoom) {
+        let id = Uuid::new_v4();
+        let room = Arc::new(Mutex::new(GameRoom::new(config, computer)));
+        self.rooms.write().expect("rooms lock poisoned").insert(id, room.clone());
+        (id, room)
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<SharedGameRoom> {
+        self.rooms.read().expect("rooms lock poisoned").get(id).cloned()
+    }
+
+    /// Adds a room restored from the database, unless it was restored concurrently, returning the one kept.
+    pub fn restore(&self, id: Uuid, room: GameRoom) -> SharedGameRoom {
+        let mut rooms = self.rooms.write().expect("rooms lock poisoned");
+        rooms.entry(id).or_insert_with(|| Arc::new(Mutex::new(room))).clone()
+    }
+
+    /// Removes the rooms that have been idle for longer than the TTL, returning how many were evicted.
+    /// Rooms that are currently locked are in use, so they're skipped.
+    pub fn evict_idle(&self) -> usize {
+        let mut rooms = self.rooms.write().expect("rooms lock poisoned");
+        let before = rooms.len();
+        rooms.retain(|_, room| match room.try_lock() {
+            Ok(room) => room.last_active.elapsed() < self.ttl,
+            Err(_) => true,
+        });
+        before - rooms.len()
+    }
+}
+
+/// Periodically evicts the idle game rooms, deleting the stored ones that no instance changed for longer than the TTL.
+pub(crate) async fn evict_idle_rooms(rooms: Arc<GameRooms>, pool: PgPool, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let evicted = rooms.evict_idle();
+        if evicted > 0 {
+            tracing::info!("Evicted {} idle game rooms", evicted);
+        }
+        match delete_idle_games(&pool, rooms.ttl).await {
+            Ok(0) => {}
+            Ok(deleted) => tracing::info!("Deleted {} idle stored game rooms", deleted),
+            Err(e) => tracing::error!("Error while deleting idle stored game rooms: {:#?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joined_room() -> (GameRoom, String, String) {
+        let mut room = GameRoom::new(GridConfig::default(), None);
+        let cookie = room.join(Player::Cookie, None).unwrap();
+        let milk = room.join(Player::Milk, None).unwrap();
+        (room, cookie, milk)
+    }
+
+    fn play(room: &mut GameRoom, column: usize) {
+        room.board.place(room.next_player(), column).unwrap();
+        room.end_turn();
+    }
+
+    #[test]
+    fn undo_is_refused_to_the_opponent_of_the_last_mover() {
+        let (mut room, _, milk) = joined_room();
+        play(&mut room, 0);
+        assert!(matches!(room.undo(Some(&milk)), Err(UndoError::Unauthorized)));
+        assert!(matches!(room.undo(None), Err(UndoError::Unauthorized)));
+        assert_eq!(room.board.history().len(), 1);
+        assert_eq!(room.next_player(), Player::Milk);
+    }
+
+    #[test]
+    fn undo_takes_back_the_move_of_the_last_mover() {
+        let (mut room, cookie, _) = joined_room();
+        play(&mut room, 0);
+        assert_eq!(room.undo(Some(&cookie)).unwrap().len(), 1);
+        assert!(room.board.history().is_empty());
+        assert_eq!(room.next_player(), Player::Cookie);
+        assert!(matches!(room.undo(Some(&cookie)), Err(UndoError::NoMove)));
+    }
+}
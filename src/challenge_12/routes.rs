@@ -1,60 +1,876 @@
-use crate::challenge_12::structs::{GameState, Player, TileType};
+use crate::challenge_12::ai::{best_move, ComputerPlayer, Difficulty, MAX_SEARCH_DEPTH};
+use crate::challenge_12::board::{GlobalBoard, SharedGlobalBoard};
+use crate::challenge_12::archive::{archive_game, finished_game, finished_games, FinishedGame};
+use crate::challenge_12::events::{publish, sse_stream, ws_session, GameEvent};
+use crate::challenge_12::persistence::{self, lock, refresh, save, StoredGame, GLOBAL_BOARD_ID};
+use crate::challenge_12::ratings::{leaderboard, player_stats, record_result, PlayerRating, PlayerStats};
+use crate::challenge_12::render::{png, svg, ImageFormat};
+use crate::challenge_12::rooms::{GameRoom, JoinError, LoadError, MoveError, SharedGameRoom, UndoError};
+use crate::challenge_12::structs::{Action, BoardView, GameState, Grid, GridConfig, Placement, Player, Variant};
+use crate::headers::wants_json;
 use crate::AppState;
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::Display;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{OwnedMutexGuard, RwLock};
+use uuid::Uuid;
 
-pub(crate) async fn board(State(state): State<Arc<RwLock<AppState>>>) -> impl IntoResponse {
+/// Renders a board either as JSON or as text, depending on what the client accepts.
+fn render(headers: &HeaderMap, text: impl Display, view: impl Serialize) -> Response {
+    if wants_json(headers) {
+        Json(view).into_response()
+    } else {
+        let board = text.to_string();
+        tracing::info!("Returning board:\n{}", board);
+        board.into_response()
+    }
+}
+
+/// Logs an error of the database holding the games, which fails the request.
+fn database_error(e: sqlx::Error) -> StatusCode {
+    tracing::error!("Error while accessing stored game: {:#?}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// Locks the global board, bringing it up to date with its stored state first, since another instance may have
+/// changed it. The global state is only read-locked for the lookup itself, as for the game rooms.
+async fn fresh_board(state: &Arc<RwLock<AppState>>) -> Result<OwnedMutexGuard<GlobalBoard>, StatusCode> {
+    let (board, pool) = global_board(state).await;
+    let mut global = board.lock_owned().await;
+    refresh(&pool, &GLOBAL_BOARD_ID, &mut *global).await.map_err(database_error)?;
+    Ok(global)
+}
+
+/// Locks the global board along with its stored state, to change it. The change must be stored with `save_board`,
+/// which releases the stored board.
+async fn lock_board(
+    state: &Arc<RwLock<AppState>>,
+) -> Result<(OwnedMutexGuard<GlobalBoard>, Transaction<'static, Postgres>), StatusCode> {
+    let (board, pool) = global_board(state).await;
+    let mut global = board.lock_owned().await;
+    let tx = lock(&pool, &GLOBAL_BOARD_ID, &mut *global).await.map_err(database_error)?;
+    Ok((global, tx))
+}
+
+async fn global_board(state: &Arc<RwLock<AppState>>) -> (SharedGlobalBoard, PgPool) {
     let locked_state = state.read().await;
-    let board = locked_state.board.to_string();
-    tracing::info!("Returning board:\n{}", board);
-    board
+    (locked_state.board.clone(), locked_state.pool.clone())
+}
+
+async fn save_board(tx: Transaction<'static, Postgres>, global: &mut GlobalBoard) -> Result<(), StatusCode> {
+    save(tx, &GLOBAL_BOARD_ID, global).await.map_err(database_error)
 }
 
-pub(crate) async fn reset_board(State(state): State<Arc<RwLock<AppState>>>) -> impl IntoResponse {
-    let mut locked_state = state.write().await;
-    locked_state.reset_board();
-    let board = locked_state.board.to_string();
+pub(crate) async fn board(State(state): State<Arc<RwLock<AppState>>>, headers: HeaderMap) -> Result<Response, StatusCode> {
+    let global = fresh_board(&state).await?;
+    Ok(render(&headers, &global.board, global.board.view()))
+}
+
+/// Resets the board, optionally changing its dimensions and win length through the `rows`, `cols` and `win` query
+/// parameters. Missing parameters fall back to the classic 4x4 board with connect-4.
+pub(crate) async fn reset_board(State(state): State<Arc<RwLock<AppState>>>, Query(config): Query<GridConfig>) -> Result<String, StatusCode> {
+    if !config.is_valid() {
+        tracing::info!("Error: invalid grid configuration {:?}", config);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let (mut global, tx) = lock_board(&state).await?;
+    global.reset(config);
+    save_board(tx, &mut global).await?;
+    let board = global.board.to_string();
+    publish(&global.events, GameEvent::Reset { board: board.clone() });
     tracing::info!("Returning board:\n{}", board);
-    board
+    Ok(board)
 }
 
-pub(crate) async fn place(State(state): State<Arc<RwLock<AppState>>>, Path((team, column)): Path<(String, usize)>) -> impl IntoResponse {
+pub(crate) async fn random_board(State(state): State<Arc<RwLock<AppState>>>) -> Result<String, StatusCode> {
+    let (mut global, tx) = lock_board(&state).await?;
+    global.randomize();
+    save_board(tx, &mut global).await?;
+    let board = global.board.to_string();
+    tracing::info!("Returning random board:\n{}", board);
+    Ok(board)
+}
+
+/// The row of the slot to fill, counted from 1 at the top, for games without gravity.
+#[derive(Deserialize, Debug)]
+pub(crate) struct PlaceQuery {
+    row: Option<usize>,
+}
+
+/// Places a tile on the global board. As in the original challenge, either team can move at any time and without a
+/// token, and the board doesn't name a next player: turns and players are only enforced in game rooms.
+pub(crate) async fn place(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path((team, column)): Path<(String, usize)>,
+    Query(query): Query<PlaceQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
     tracing::info!("Placing entry in grid:\n{}\n{}", team, column);
-    let mut locked_state = state.write().await;
-    let player = match Player::try_from(team.as_str()) {
-        Ok(player) => player,
+    play(state, &team, BoardMove::Place { column, row: query.row }, headers).await
+}
+
+/// Pops the bottom tile of a column out, in the pop-out variant.
+pub(crate) async fn pop(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path((team, column)): Path<(String, usize)>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    tracing::info!("Popping entry out of grid:\n{}\n{}", team, column);
+    play(state, &team, BoardMove::Pop { column }, headers).await
+}
+
+async fn play(state: Arc<RwLock<AppState>>, team: &str, board_move: BoardMove, headers: HeaderMap) -> Result<Response, StatusCode> {
+    let player = parse_player(team)?;
+    let (mut global, tx) = lock_board(&state).await?;
+    match play_on_board(&mut global.board, player, board_move) {
+        Ok(_) => {}
+        Err(StatusCode::BAD_REQUEST) => return Err(StatusCode::BAD_REQUEST),
+        Err(status) => return Ok((status, render(&headers, &global.board, global.board.view())).into_response()),
+    };
+    save_board(tx, &mut global).await?;
+    if let Some(event) = GameEvent::last_move(&global.board, global.board.to_string()) {
+        publish(&global.events, event);
+    }
+    let response = render(&headers, &global.board, global.board.view());
+    let finished = finished_board(&global.board);
+    drop(global);
+    if let Some(board) = finished {
+        let pool = state.read().await.pool.clone();
+        archive(&pool, None, &board, None).await;
+    }
+    Ok(response)
+}
+
+/// Lists the moves played on the board since it was reset.
+pub(crate) async fn history(State(state): State<Arc<RwLock<AppState>>>) -> Result<Json<Vec<Placement>>, StatusCode> {
+    Ok(Json(fresh_board(&state).await?.board.history()))
+}
+
+/// Renders every position of the board since it was reset, or only the one at the `step` query parameter.
+pub(crate) async fn replay(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Query(query): Query<ReplayQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    render_replay(&headers, &fresh_board(&state).await?.board, &query)
+}
+
+/// Draws the board, or its position at the `step` query parameter, as an SVG or PNG image.
+pub(crate) async fn board_image(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(format): Path<ImageFormat>,
+    Query(query): Query<ReplayQuery>,
+) -> Result<Response, StatusCode> {
+    render_image(&fresh_board(&state).await?.board, format, &query)
+}
+
+/// Takes back the last move played on the board.
+pub(crate) async fn undo(State(state): State<Arc<RwLock<AppState>>>, headers: HeaderMap) -> Result<Response, StatusCode> {
+    let (mut global, tx) = lock_board(&state).await?;
+    let placement = global.board.undo().ok_or_else(|| {
+        tracing::info!("Error: no move to undo");
+        StatusCode::CONFLICT
+    })?;
+    save_board(tx, &mut global).await?;
+    tracing::info!("Took back {:?}", placement);
+    let board = global.board.to_string();
+    publish(&global.events, GameEvent::Undo { undone_move: placement, board });
+    Ok(render(&headers, &global.board, global.board.view()))
+}
+
+/// Replaces the board with a position in the compact form returned in the `fen` field of its JSON representation.
+pub(crate) async fn load_board(State(state): State<Arc<RwLock<AppState>>>, headers: HeaderMap, body: String) -> Result<Response, StatusCode> {
+    let grid = match Grid::from_fen(body.trim()) {
+        Ok(grid) => grid,
         Err(_) => {
-            tracing::info!("Error: Invalid player");
-            return StatusCode::BAD_REQUEST.into_response();
+            tracing::info!("Error: invalid position {:?}", body);
+            return Err(StatusCode::BAD_REQUEST);
         }
     };
-    if !(1..=4).contains(&column) {
-        tracing::info!("Error: column not in 1-4");
-        return StatusCode::BAD_REQUEST.into_response();
+    let (mut global, tx) = lock_board(&state).await?;
+    global.board = grid;
+    save_board(tx, &mut global).await?;
+    publish(&global.events, GameEvent::Reset { board: global.board.to_string() });
+    Ok(render(&headers, &global.board, global.board.view()))
+}
+
+fn parse_player(team: &str) -> Result<Player, StatusCode> {
+    Player::try_from(team).map_err(|_| {
+        tracing::info!("Error: Invalid player");
+        StatusCode::BAD_REQUEST
+    })
+}
+
+/// A move as requested by a player, with its column and row starting from 1.
+#[derive(Debug, Copy, Clone)]
+enum BoardMove {
+    /// Drops a tile in a column, or places it in the given row of that column when there's no gravity.
+    Place { column: usize, row: Option<usize> },
+    Pop { column: usize },
+}
+
+/// Plays a move of the given player, checking that it's allowed by the rules variant of the board.
+/// Errors other than invalid moves are meant to come with the board as their body.
+fn play_on_board(board: &mut Grid, player: Player, board_move: BoardMove) -> Result<(), StatusCode> {
+    let config = board.config();
+    let (column, row) = match board_move {
+        BoardMove::Place { column, row } => (column, row),
+        BoardMove::Pop { column } => (column, None),
+    };
+    if !(1..=config.cols).contains(&column) {
+        tracing::info!("Error: column not in 1-{}", config.cols);
+        return Err(StatusCode::BAD_REQUEST);
     }
-    match locked_state.board.check_winner() {
+    let valid_move = match (board_move, config.variant) {
+        (BoardMove::Place { .. }, Variant::Free) => row.is_some_and(|row| (1..=config.rows).contains(&row)),
+        (BoardMove::Place { .. }, _) => row.is_none(),
+        (BoardMove::Pop { .. }, variant) => variant == Variant::PopOut,
+    };
+    if !valid_move {
+        tracing::info!("Error: {:?} not allowed in the {} variant", board_move, config.variant.name());
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match board.check_winner() {
         GameState::Win(_) | GameState::NoWin => {
             tracing::info!("Error: game finished");
-            (StatusCode::SERVICE_UNAVAILABLE, locked_state.board.to_string()).into_response()
+            Err(StatusCode::SERVICE_UNAVAILABLE)
         }
         GameState::Pending => {
             tracing::info!("Game pending");
-            let place_result = locked_state.board.place(TileType::from(&player), column - 1);
-            tracing::info!("Result of placement: {:?}", place_result);
-            match place_result {
+            let move_result = match (board_move, row) {
+                (BoardMove::Place { .. }, Some(row)) => board.place_at(player, row - 1, column - 1).map(|_| ()),
+                (BoardMove::Place { .. }, None) => board.place(player, column - 1).map(|_| ()),
+                (BoardMove::Pop { .. }, _) => board.pop(player, column - 1),
+            };
+            tracing::info!("Result of move: {:?}", move_result);
+            match move_result {
                 Ok(_) => {
-                    let board = locked_state.board.to_string();
                     tracing::info!("Returning board:\n{}", board);
-                    board.into_response()
+                    Ok(())
                 }
-                Err(_) => {
-                    tracing::info!("Error: column full");
-                    (StatusCode::SERVICE_UNAVAILABLE, locked_state.board.to_string()).into_response()
+                Err(error) => {
+                    tracing::info!("Error: {:?}", error);
+                    Err(StatusCode::SERVICE_UNAVAILABLE)
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Returns a copy of the board if the game is over.
+fn finished_board(board: &Grid) -> Option<Grid> {
+    (!matches!(board.check_winner(), GameState::Pending)).then(|| board.clone())
+}
+
+/// Stores a finished game, so that it can be replayed later, and updates the ratings of its named participants.
+/// Failing to do so doesn't affect the game itself. Finished games can't be taken back in game rooms, so that their
+/// result is only recorded once, whereas the global board is stored again when it's finished again after an undo.
+async fn archive(pool: &PgPool, game: Option<Uuid>, board: &Grid, participants: Option<(String, String)>) {
+    let id = match archive_game(pool, game, board).await {
+        Ok(id) => id,
+        Err(e) => {
+            tracing::error!("Error while archiving finished game: {:#?}", e);
+            return;
+        }
+    };
+    tracing::info!("Archived finished game {}", id);
+    if let Some(participants) = participants {
+        match record_result(pool, id, &participants, board.check_winner()).await {
+            Ok(_) => tracing::info!("Recorded result of {} against {}", participants.0, participants.1),
+            Err(e) => tracing::error!("Error while recording game result: {:#?}", e),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ReplayQuery {
+    step: Option<usize>,
+}
+
+/// Renders every position of a game from its start, the first one being the starting position.
+fn render_replay(headers: &HeaderMap, board: &Grid, query: &ReplayQuery) -> Result<Response, StatusCode> {
+    let mut positions = board.replay();
+    if let Some(step) = query.step {
+        if step >= positions.len() {
+            tracing::info!("Error: step not in 0-{}", positions.len() - 1);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        positions = vec![positions.swap_remove(step)];
+    }
+    let text: Vec<String> = positions.iter().map(Grid::to_string).collect();
+    let views: Vec<BoardView> = positions.iter().map(Grid::view).collect();
+    Ok(render(headers, text.join("\n"), views))
+}
+
+/// Draws a game, or its position at the given step of its replay.
+fn render_image(board: &Grid, format: ImageFormat, query: &ReplayQuery) -> Result<Response, StatusCode> {
+    let position = match query.step {
+        Some(step) => board.replay().into_iter().nth(step).ok_or_else(|| {
+            tracing::info!("Error: no step {} in game", step);
+            StatusCode::BAD_REQUEST
+        })?,
+        None => board.clone(),
+    };
+    let image = match format {
+        ImageFormat::Svg => svg(&position).into_bytes(),
+        ImageFormat::Png => png(&position).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    };
+    Ok(([(header::CONTENT_TYPE, format.content_type())], image).into_response())
+}
+
+/// Looks up a game room, loading it from the database if this instance doesn't have it in memory, because another
+/// instance created it or because it was evicted. The global state is only read-locked for the lookup itself, so that
+/// moves in a room only ever lock that room.
+async fn game_room(state: &Arc<RwLock<AppState>>, id: &Uuid) -> Result<(SharedGameRoom, PgPool), StatusCode> {
+    let (rooms, pool) = {
+        let locked_state = state.read().await;
+        (locked_state.rooms.clone(), locked_state.pool.clone())
+    };
+    if let Some(room) = rooms.get(id) {
+        return Ok((room, pool));
+    }
+    let stored = match *id {
+        GLOBAL_BOARD_ID => None,
+        _ => persistence::load_game(&pool, id).await.map_err(database_error)?,
+    };
+    match stored {
+        Some((snapshot, version)) => {
+            tracing::info!("Loaded game {} from the database", id);
+            Ok((rooms.restore(*id, GameRoom::from_snapshot(snapshot, version)), pool))
+        }
+        None => {
+            tracing::info!("Error: unknown game {}", id);
+            Err(StatusCode::NOT_FOUND)
+        }
+    }
+}
+
+/// Locks a game room to read it, bringing it up to date with its stored state first.
+async fn fresh_room(state: &Arc<RwLock<AppState>>, id: &Uuid) -> Result<OwnedMutexGuard<GameRoom>, StatusCode> {
+    let (room, pool) = game_room(state, id).await?;
+    let mut room = room.lock_owned().await;
+    room.touch();
+    refresh(&pool, id, &mut *room).await.map_err(database_error)?;
+    Ok(room)
+}
+
+/// Locks a game room along with its stored state, to change it. The change must be stored with `save_room`, which
+/// releases the stored state.
+async fn lock_room(
+    state: &Arc<RwLock<AppState>>,
+    id: &Uuid,
+) -> Result<(OwnedMutexGuard<GameRoom>, Transaction<'static, Postgres>), StatusCode> {
+    let (room, pool) = game_room(state, id).await?;
+    let mut room = room.lock_owned().await;
+    room.touch();
+    let tx = lock(&pool, id, &mut *room).await.map_err(database_error)?;
+    Ok((room, tx))
+}
+
+async fn save_room(tx: Transaction<'static, Postgres>, id: &Uuid, room: &mut GameRoom) -> Result<(), StatusCode> {
+    save(tx, id, room).await.map_err(database_error)
+}
+
+/// Lets the server play one of the sides of a game, with a search depth given either directly or through a
+/// difficulty level.
+#[derive(Deserialize, Debug)]
+pub(crate) struct OpponentOptions {
+    opponent: Option<Player>,
+    difficulty: Option<Difficulty>,
+    depth: Option<u32>,
+}
+
+impl OpponentOptions {
+    fn computer_player(&self) -> Result<Option<ComputerPlayer>, StatusCode> {
+        let player = match self.opponent {
+            Some(player) => player,
+            None => return Ok(None),
+        };
+        let difficulty = self.difficulty.unwrap_or_default();
+        let depth = self.depth.unwrap_or(difficulty.search_depth());
+        if !(1..=MAX_SEARCH_DEPTH).contains(&depth) {
+            tracing::info!("Error: search depth not in 1-{}", MAX_SEARCH_DEPTH);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(Some(ComputerPlayer { player, difficulty, depth }))
+    }
+}
+
+/// Lets the computer player move, if it's its turn, and stores its move. The search runs on a blocking thread, since
+/// it can take a while on larger boards, on a copy of the board, with neither the room nor its stored state locked.
+/// The move is dropped if the room changed during the search. The move of the human player being stored already,
+/// failing to play the computer move doesn't fail the request: the room is returned as it is.
+async fn play_computer_turn(
+    state: &Arc<RwLock<AppState>>,
+    id: &Uuid,
+    room: OwnedMutexGuard<GameRoom>,
+) -> OwnedMutexGuard<GameRoom> {
+    let computer = match room.computer_to_move() {
+        Some(computer) => computer,
+        None => return room,
+    };
+    let (grid, version) = (room.board.clone(), room.version());
+    let shared_room = OwnedMutexGuard::mutex(&room).clone();
+    drop(room);
+    let search = tokio::task::spawn_blocking(move || computer.choose_move(&grid)).await;
+    let mut room = shared_room.lock_owned().await;
+    let computer_move = match search {
+        Ok(Some(computer_move)) => computer_move,
+        Ok(None) => return room,
+        Err(e) => {
+            tracing::error!("Error while searching the computer move: {:?}", e);
+            return room;
+        }
+    };
+    let pool = state.read().await.pool.clone();
+    let tx = match lock(&pool, id, &mut *room).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            tracing::error!("Error while locking game {} for the computer move: {:#?}", id, e);
+            return room;
+        }
+    };
+    if room.version() != version {
+        tracing::info!("Game {} changed during the search, dropping the computer move", id);
+        return room;
+    }
+    let before = room.snapshot();
+    tracing::info!("Computer plays {:?}", room.board.describe(&computer_move));
+    if room.board.play(computer_move).is_ok() {
+        room.end_turn();
+        room.publish_move();
+    }
+    if let Err(e) = save(tx, id, &mut *room).await {
+        tracing::error!("Error while storing the computer move in game {}: {:#?}", id, e);
+        room.restore(before, version);
+    }
+    room
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct CreatedGame {
+    id: Uuid,
+}
+
+/// Creates a new game room, accepting the same query parameters as the reset endpoint.
+/// The `opponent` query parameter makes the server play the given side, answering every move of the other one.
+pub(crate) async fn create_game(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Query(config): Query<GridConfig>,
+    Query(options): Query<OpponentOptions>,
+) -> Result<Response, StatusCode> {
+    if !config.is_valid() {
+        tracing::info!("Error: invalid grid configuration {:?}", config);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let computer = options.computer_player()?;
+    let (rooms, pool) = {
+        let locked_state = state.read().await;
+        (locked_state.rooms.clone(), locked_state.pool.clone())
+    };
+    let (id, room) = rooms.create(config, computer);
+    tracing::info!("Created game {}", id);
+    let mut room = room.lock_owned().await;
+    let tx = lock(&pool, &id, &mut *room).await.map_err(database_error)?;
+    save_room(tx, &id, &mut room).await?;
+    play_computer_turn(&state, &id, room).await;
+    Ok((StatusCode::CREATED, Json(CreatedGame { id })).into_response())
+}
+
+pub(crate) async fn game_board(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let room = fresh_room(&state, &id).await?;
+    tracing::info!("Returning board of game {}", id);
+    Ok(render(&headers, &*room, room.view()))
+}
+
+/// Starts a new game in a room, for any of its players.
+pub(crate) async fn reset_game(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(id): Path<Uuid>,
+    Query(config): Query<GridConfig>,
+    headers: HeaderMap,
+) -> Result<String, StatusCode> {
+    if !config.is_valid() {
+        tracing::info!("Error: invalid grid configuration {:?}", config);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let (mut room, tx) = lock_room(&state, &id).await?;
+    if !room.is_seated(bearer_token(&headers)) {
+        tracing::info!("Error: not a player of game {}", id);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    room.reset(config);
+    save_room(tx, &id, &mut room).await?;
+    let room = play_computer_turn(&state, &id, room).await;
+    let board = room.to_string();
+    tracing::info!("Returning board of game {}:\n{}", id, board);
+    Ok(board)
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct JoinedGame {
+    player: Player,
+    token: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct JoinQuery {
+    name: Option<String>,
+}
+
+/// Takes a side in a game, returning the token to send as a bearer token in the Authorization header when moving.
+/// Games finished between two players who gave their `name` in the query string count for the leaderboard.
+pub(crate) async fn join_game(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path((id, team)): Path<(Uuid, String)>,
+    Query(query): Query<JoinQuery>,
+) -> Result<Response, StatusCode> {
+    let player = parse_player(&team)?;
+    let (mut room, tx) = lock_room(&state, &id).await?;
+    match room.join(player, query.name) {
+        Ok(token) => {
+            save_room(tx, &id, &mut room).await?;
+            tracing::info!("{:?} joined game {}", player, id);
+            Ok((StatusCode::CREATED, Json(JoinedGame { player, token })).into_response())
+        }
+        Err(JoinError::SeatTaken) => {
+            tracing::info!("Error: {:?} already taken in game {}", player, id);
+            Err(StatusCode::CONFLICT)
+        }
+        Err(JoinError::InvalidName) => {
+            tracing::info!("Error: invalid player name");
+            Err(StatusCode::BAD_REQUEST)
+        }
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+pub(crate) async fn place_in_game(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path((id, team, column)): Path<(Uuid, String, usize)>,
+    Query(query): Query<PlaceQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    tracing::info!("Placing entry in game {}:\n{}\n{}", id, team, column);
+    play_in_game(state, id, &team, BoardMove::Place { column, row: query.row }, headers).await
+}
+
+pub(crate) async fn pop_in_game(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path((id, team, column)): Path<(Uuid, String, usize)>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    tracing::info!("Popping entry out of game {}:\n{}\n{}", id, team, column);
+    play_in_game(state, id, &team, BoardMove::Pop { column }, headers).await
+}
+
+async fn play_in_game(
+    state: Arc<RwLock<AppState>>,
+    id: Uuid,
+    team: &str,
+    board_move: BoardMove,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let player = parse_player(team)?;
+    let (mut room, tx) = lock_room(&state, &id).await?;
+    match room.check_move(player, bearer_token(&headers)) {
+        Ok(_) => {}
+        Err(MoveError::Unauthorized) => {
+            tracing::info!("Error: {:?} not authorized in game {}", player, id);
+            return Err(StatusCode::FORBIDDEN);
+        }
+        Err(MoveError::OutOfTurn) => {
+            tracing::info!("Error: not {:?}'s turn in game {}", player, id);
+            return Ok((StatusCode::CONFLICT, render(&headers, &*room, room.view())).into_response());
+        }
+    }
+    match play_on_board(&mut room.board, player, board_move) {
+        Ok(_) => {}
+        Err(StatusCode::BAD_REQUEST) => return Err(StatusCode::BAD_REQUEST),
+        Err(status) => return Ok((status, render(&headers, &*room, room.view())).into_response()),
+    };
+    room.end_turn();
+    room.publish_move();
+    save_room(tx, &id, &mut room).await?;
+    let room = play_computer_turn(&state, &id, room).await;
+    let response = render(&headers, &*room, room.view());
+    let finished = finished_board(&room.board);
+    let participants = room.rated_participants();
+    drop(room);
+    if let Some(board) = finished {
+        let pool = state.read().await.pool.clone();
+        archive(&pool, Some(id), &board, participants).await;
+    }
+    Ok(response)
+}
+
+pub(crate) async fn game_history(State(state): State<Arc<RwLock<AppState>>>, Path(id): Path<Uuid>) -> Result<Json<Vec<Placement>>, StatusCode> {
+    let room = fresh_room(&state, &id).await?;
+    Ok(Json(room.board.history()))
+}
+
+pub(crate) async fn game_replay(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ReplayQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let room = fresh_room(&state, &id).await?;
+    render_replay(&headers, &room.board, &query)
+}
+
+pub(crate) async fn game_image(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path((id, format)): Path<(Uuid, ImageFormat)>,
+    Query(query): Query<ReplayQuery>,
+) -> Result<Response, StatusCode> {
+    let room = fresh_room(&state, &id).await?;
+    render_image(&room.board, format, &query)
+}
+
+/// Takes back the last move of a game, for the player who made it. Against the computer, this takes back its answer too.
+/// Finished games are final, since their result already counts for the ratings.
+pub(crate) async fn undo_game(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let (mut room, tx) = lock_room(&state, &id).await?;
+    if !room.is_seated(bearer_token(&headers)) {
+        tracing::info!("Error: not a player of game {}", id);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if !matches!(room.board.check_winner(), GameState::Pending) {
+        tracing::info!("Error: game {} finished", id);
+        return Err(StatusCode::CONFLICT);
+    }
+    let undone = match room.undo(bearer_token(&headers)) {
+        Ok(undone) => undone,
+        Err(UndoError::NoMove) => {
+            tracing::info!("Error: no move to undo in game {}", id);
+            return Err(StatusCode::CONFLICT);
+        }
+        Err(UndoError::Unauthorized) => {
+            tracing::info!("Error: last move of game {} not made by the player taking it back", id);
+            return Err(StatusCode::FORBIDDEN);
+        }
+    };
+    tracing::info!("Took back {:?} in game {}", undone, id);
+    save_room(tx, &id, &mut room).await?;
+    let room = play_computer_turn(&state, &id, room).await;
+    Ok(render(&headers, &*room, room.view()))
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct FinishedGamesQuery {
+    game: Option<Uuid>,
+}
+
+/// Lists the finished games, optionally only the ones played in the game room given by the `game` query parameter.
+pub(crate) async fn list_finished_games(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Query(query): Query<FinishedGamesQuery>,
+) -> Result<Json<Vec<FinishedGame>>, StatusCode> {
+    let pool = state.read().await.pool.clone();
+    finished_games(&pool, query.game).await.map(Json).map_err(|e| {
+        tracing::error!("Error while listing finished games: {:#?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// Lists the best rated players, along with their amount of wins, losses and draws.
+pub(crate) async fn get_leaderboard(State(state): State<Arc<RwLock<AppState>>>) -> Result<Json<Vec<PlayerRating>>, StatusCode> {
+    let pool = state.read().await.pool.clone();
+    leaderboard(&pool).await.map(Json).map_err(|e| {
+        tracing::error!("Error while fetching leaderboard: {:#?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// Returns the rating of a player, along with the result of each of their games and their rating after it.
+pub(crate) async fn get_player_stats(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(name): Path<String>,
+) -> Result<Json<PlayerStats>, StatusCode> {
+    let pool = state.read().await.pool.clone();
+    match player_stats(&pool, &name).await {
+        Ok(Some(stats)) => Ok(Json(stats)),
+        Ok(None) => {
+            tracing::info!("Error: unknown player {}", name);
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            tracing::error!("Error while fetching player stats: {:#?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Renders every position of a finished game, just like the replay of a game in progress.
+pub(crate) async fn replay_finished_game(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ReplayQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let board = finished_board_of(&state, &id).await?;
+    render_replay(&headers, &board, &query)
+}
+
+pub(crate) async fn finished_game_image(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path((id, format)): Path<(Uuid, ImageFormat)>,
+    Query(query): Query<ReplayQuery>,
+) -> Result<Response, StatusCode> {
+    let board = finished_board_of(&state, &id).await?;
+    render_image(&board, format, &query)
+}
+
+/// Fetches a finished game, playing its moves again to get its final board.
+async fn finished_board_of(state: &Arc<RwLock<AppState>>, id: &Uuid) -> Result<Grid, StatusCode> {
+    let pool = state.read().await.pool.clone();
+    let game = match finished_game(&pool, id).await {
+        Ok(Some(game)) => game,
+        Ok(None) => {
+            tracing::info!("Error: unknown finished game {}", id);
+            return Err(StatusCode::NOT_FOUND);
+        }
+        Err(e) => {
+            tracing::error!("Error while fetching finished game: {:#?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    game.board().map_err(|_| {
+        tracing::error!("Error: finished game {} can't be replayed", id);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// Replaces the board of a game with a position in the compact form returned in the `fen` field of its JSON
+/// representation, for any of its players. The position must be reachable with the players taking turns.
+/// Games played from a loaded position don't count for the ratings.
+pub(crate) async fn load_game(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Response, StatusCode> {
+    let grid = Grid::from_fen(body.trim()).map_err(|_| {
+        tracing::info!("Error: invalid position {:?}", body);
+        StatusCode::BAD_REQUEST
+    })?;
+    let (mut room, tx) = lock_room(&state, &id).await?;
+    if !room.is_seated(bearer_token(&headers)) {
+        tracing::info!("Error: not a player of game {}", id);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match room.load(grid) {
+        Ok(_) => {}
+        Err(LoadError::UnbalancedTiles) => {
+            tracing::info!("Error: unbalanced position {:?}", body);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    save_room(tx, &id, &mut room).await?;
+    let room = play_computer_turn(&state, &id, room).await;
+    tracing::info!("Loaded position in game {}", id);
+    Ok(render(&headers, &*room, room.view()))
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct HintQuery {
+    depth: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct Hint {
+    player: Player,
+    action: Action,
+    column: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    row: Option<usize>,
+}
+
+/// Suggests the best move for the player whose turn it is, without playing it, among the moves the rules variant
+/// allows: a tile to drop or, in the pop-out variant, to pop out of a column. Without gravity, the row of the slot to
+/// fill is suggested as well.
+pub(crate) async fn hint(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<HintQuery>,
+) -> Result<Json<Hint>, StatusCode> {
+    let room = fresh_room(&state, &id).await?;
+    let depth = query
+        .depth
+        .or(room.computer().map(|computer| computer.depth))
+        .unwrap_or(Difficulty::default().search_depth());
+    if !(1..=MAX_SEARCH_DEPTH).contains(&depth) {
+        tracing::info!("Error: search depth not in 1-{}", MAX_SEARCH_DEPTH);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if !matches!(room.board.check_winner(), GameState::Pending) {
+        tracing::info!("Error: game finished");
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    let player = room.next_player();
+    // The room is released during the search, which only needs a copy of the board
+    let grid = room.board.clone();
+    let variant = grid.config().variant;
+    drop(room);
+    let placement = tokio::task::spawn_blocking(move || best_move(&grid, player, depth).map(|best| grid.describe(&best)))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let row = (variant == Variant::Free).then_some(placement.row);
+    Ok(Json(Hint { player, action: placement.action, column: placement.column, row }))
+}
+
+/// Streams the moves and resets of the board as server-sent events.
+pub(crate) async fn board_events(State(state): State<Arc<RwLock<AppState>>>) -> Result<impl IntoResponse, StatusCode> {
+    let global = fresh_board(&state).await?;
+    let snapshot = GameEvent::Snapshot { board: global.board.to_string() };
+    Ok(sse_stream(snapshot, global.events.subscribe()))
+}
+
+/// Pushes the moves and resets of the board through a WebSocket.
+pub(crate) async fn board_socket(State(state): State<Arc<RwLock<AppState>>>, upgrade: WebSocketUpgrade) -> Result<impl IntoResponse, StatusCode> {
+    let global = fresh_board(&state).await?;
+    let snapshot = GameEvent::Snapshot { board: global.board.to_string() };
+    let receiver = global.events.subscribe();
+    Ok(upgrade.on_upgrade(move |socket| ws_session(socket, snapshot, receiver)))
+}
+
+/// Streams the moves and resets of a game as server-sent events.
+pub(crate) async fn game_events(State(state): State<Arc<RwLock<AppState>>>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, StatusCode> {
+    let room = fresh_room(&state, &id).await?;
+    let (snapshot, receiver) = room.subscribe();
+    Ok(sse_stream(snapshot, receiver))
+}
+
+/// Pushes the moves and resets of a game through a WebSocket.
+pub(crate) async fn game_socket(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(id): Path<Uuid>,
+    upgrade: WebSocketUpgrade,
+) -> Result<impl IntoResponse, StatusCode> {
+    let room = fresh_room(&state, &id).await?;
+    let (snapshot, receiver) = room.subscribe();
+    Ok(upgrade.on_upgrade(move |socket| ws_session(socket, snapshot, receiver)))
+}
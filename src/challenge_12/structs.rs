@@ -1,6 +1,9 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub(crate) enum TileType {
     Empty,
     Cookie,
@@ -25,11 +28,28 @@ impl From<&Player> for TileType {
         }
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub(crate) enum Player {
     Cookie,
     Milk,
 }
+impl Player {
+    /// The name of the player, as used in paths and in the database.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Player::Cookie => "cookie",
+            Player::Milk => "milk",
+        }
+    }
+
+    pub fn opponent(&self) -> Self {
+        match self {
+            Player::Cookie => Player::Milk,
+            Player::Milk => Player::Cookie,
+        }
+    }
+}
 impl TryFrom<&TileType> for Player {
     type Error = ();
     fn try_from(t: &TileType) -> Result<Self, Self::Error> {
@@ -52,44 +72,164 @@ impl TryFrom<&str> for Player {
         }
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(tag = "state", content = "winner", rename_all = "lowercase")]
 pub(crate) enum GameState {
     Win(Player),
+    #[serde(rename = "draw")]
     NoWin,
     Pending,
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Largest amount of rows and columns a grid can have.
+const MAX_GRID_SIZE: usize = 16;
+
+/// Rules for moving: dropping tiles in columns, dropping them or popping out one's own bottom tile of a column, or
+/// placing them anywhere without gravity.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Variant {
+    #[default]
+    Classic,
+    #[serde(rename = "popout")]
+    PopOut,
+    Free,
+}
+impl Variant {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variant::Classic => "classic",
+            Variant::PopOut => "popout",
+            Variant::Free => "free",
+        }
+    }
+}
+impl TryFrom<&str> for Variant {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "classic" => Ok(Variant::Classic),
+            "popout" => Ok(Variant::PopOut),
+            "free" => Ok(Variant::Free),
+            _ => Err(())
+        }
+    }
+}
+
+/// Dimensions of a grid, the amount of tiles in a line needed to win and the rules variant, as given in the query
+/// string when resetting.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct GridConfig {
+    pub rows: usize,
+    pub cols: usize,
+    #[serde(rename = "win")]
+    pub win_length: usize,
+    pub variant: Variant,
+}
+impl Default for GridConfig {
+    fn default() -> Self {
+        Self {
+            rows: 4,
+            cols: 4,
+            win_length: 4,
+            variant: Variant::Classic,
+        }
+    }
+}
+impl GridConfig {
+    /// A win length longer than both dimensions would make the game unwinnable, so it's rejected.
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_GRID_SIZE).contains(&self.rows)
+            && (1..=MAX_GRID_SIZE).contains(&self.cols)
+            && (2..=self.rows.max(self.cols)).contains(&self.win_length)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum PlaceError {
+    InvalidColumn,
+    InvalidRow,
+    ColumnFull,
+    SlotTaken,
+    /// Players can only pop out their own tiles.
+    NotOwnTile,
+    /// A move the rules variant of the grid doesn't allow in the current position.
+    IllegalMove,
+}
+
+/// A move recorded in the history of a grid, or explored by the computer player.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Move {
+    /// A tile placed at the given position, whether it was dropped there or not.
+    Place(Player, usize),
+    /// The bottom tile of the given column popped out, the tiles above it falling down.
+    Pop(Player, usize),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Action {
+    Place,
+    Pop,
+}
+
+/// A tile placed in or popped out of a grid, with its row and column counted from 1, starting from the top left
+/// corner.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Placement {
+    pub player: Player,
+    pub row: usize,
+    pub column: usize,
+    pub action: Action,
+}
+
+/// Machine-readable representation of a grid.
+#[derive(Debug, Serialize)]
+pub(crate) struct BoardView {
+    rows: usize,
+    cols: usize,
+    win_length: usize,
+    variant: Variant,
+    cells: Vec<Vec<TileType>>,
+    last_move: Option<Placement>,
+    empty_slots_left: usize,
+    #[serde(flatten)]
+    state: GameState,
+    fen: String,
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct Grid {
-    grid: [TileType; 16], // every 4 elements makes up a row
-    last_placed: Option<usize>,
+    config: GridConfig,
+    grid: Vec<TileType>, // every `cols` elements make up a row, starting from the top one
+    moves: Vec<Move>, // every move made since the grid was created or loaded, in order
     empty_slots_left: usize,
 }
 impl Default for Grid {
     fn default() -> Self {
-        Self {
-            grid: [TileType::Empty; 16],
-            last_placed: None,
-            empty_slots_left: 16,
-        }
+        Self::new(GridConfig::default())
     }
 }
 
 impl Display for Grid {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let mut result = Vec::new();
-        for row in self.grid.chunks_exact(4) {
-            let [a, b, c, d] = <&[TileType; 4]>::try_from(row).expect("4 items");
-            result.push(format!("⬜{}{}{}{}⬜", a, b, c, d));
+        let wall = TileType::Wall;
+        for row in self.grid.chunks_exact(self.config.cols) {
+            write!(f, "{}", wall)?;
+            for tile in row {
+                write!(f, "{}", tile)?;
+            }
+            writeln!(f, "{}", wall)?;
         }
-        result.push("⬜⬜⬜⬜⬜⬜\n".to_string());
-        write!(f, "{}", result.join("\n"))?;
+        writeln!(f, "{}", wall.to_string().repeat(self.config.cols + 2))?;
         match self.check_winner() {
             GameState::Win(player) => {
-                write!(f, "{} wins!\n", TileType::from(&player))?;
+                writeln!(f, "{} wins!", TileType::from(&player))?;
             }
             GameState::NoWin => {
-                write!(f, "No winner.\n")?;
+                writeln!(f, "No winner.")?;
             }
             _ => {}
         }
@@ -97,68 +237,501 @@ impl Display for Grid {
     }
 }
 
+/// Directions a winning line can follow, as (row, column) steps: rows, columns, diagonals and anti-diagonals.
+const DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
 impl Grid {
-    // todo clean up this mess
-    pub fn check_winner(&self) -> GameState {
-        if let Some(last_position) = self.last_placed {
-            let central_slot = &self.grid[last_position];
-            let row_next_slot = &self.grid[(last_position + 1) % 4 * ((last_position % 4) + 1)];
-            let row_next_2_slots = &self.grid[(last_position + 2) % 4 * ((last_position % 4) + 1)];
-            let row_previous_slot = &self.grid[(last_position + 3) % 4 * ((last_position % 4) + 1)];
-            let column_next_slot = &self.grid[(last_position + 4) % 16];
-            let column_next_2_slot = &self.grid[(last_position + 8) % 16];
-            let column_previous_slot = &self.grid[(last_position + 12) % 16];
-            if let Ok(player) = Player::try_from(central_slot) {
-                tracing::info!("Checking row with elements: {:#?}, {:#?}, {:#?}, {:#?}", central_slot, row_next_slot, row_next_2_slots, row_previous_slot);
-                if central_slot == row_next_slot && central_slot == row_next_2_slots && central_slot == row_previous_slot {
-                    return GameState::Win(player);
+    pub fn new(config: GridConfig) -> Self {
+        Self {
+            config,
+            grid: vec![TileType::Empty; config.rows * config.cols],
+            moves: Vec::new(),
+            empty_slots_left: config.rows * config.cols,
+        }
+    }
+
+    /// Fills the whole grid, top to bottom and left to right, drawing a cookie for every `true` the generator yields
+    /// and a milk otherwise.
+    pub fn random<R: Rng>(config: GridConfig, rng: &mut R) -> Self {
+        let grid = (0..config.rows * config.cols)
+            .map(|_| if rng.gen::<bool>() { TileType::Cookie } else { TileType::Milk })
+            .collect();
+        Self {
+            config,
+            grid,
+            moves: Vec::new(),
+            empty_slots_left: 0,
+        }
+    }
+
+    pub fn config(&self) -> GridConfig {
+        self.config
+    }
+
+    pub fn describe(&self, player_move: &Move) -> Placement {
+        match *player_move {
+            Move::Place(player, position) => Placement {
+                player,
+                row: position / self.config.cols + 1,
+                column: position % self.config.cols + 1,
+                action: Action::Place,
+            },
+            Move::Pop(player, column) => Placement {
+                player,
+                row: self.config.rows,
+                column: column + 1,
+                action: Action::Pop,
+            },
+        }
+    }
+
+    /// Every move made since the grid was created or loaded, in order.
+    pub fn history(&self) -> Vec<Placement> {
+        self.moves.iter().map(|player_move| self.describe(player_move)).collect()
+    }
+
+    pub fn last_move(&self) -> Option<Placement> {
+        self.moves.last().map(|player_move| self.describe(player_move))
+    }
+
+    fn last_mover(&self) -> Option<Player> {
+        self.moves.last().map(|player_move| match *player_move {
+            Move::Place(player, _) | Move::Pop(player, _) => player,
+        })
+    }
+
+    /// Plays a move that is known to be legal, recording it.
+    fn apply(&mut self, player_move: Move) {
+        match player_move {
+            Move::Place(player, position) => {
+                self.grid[position] = TileType::from(&player);
+                self.empty_slots_left -= 1;
+            }
+            Move::Pop(_, column) => {
+                for row in (1..self.config.rows).rev() {
+                    self.grid[row * self.config.cols + column] = self.grid[(row - 1) * self.config.cols + column];
                 }
-                tracing::info!("Checking column with elements: {:#?}, {:#?}, {:#?}, {:#?}", central_slot, column_next_slot, column_next_2_slot, column_previous_slot);
-                if central_slot == column_next_slot && central_slot == column_next_2_slot && central_slot == column_previous_slot {
-                    return GameState::Win(player);
+                self.grid[column] = TileType::Empty;
+                self.empty_slots_left += 1;
+            }
+        }
+        self.moves.push(player_move);
+    }
+
+    /// The grid as it was before any of the moves in its history.
+    pub fn start(&self) -> Self {
+        let mut grid = self.clone();
+        while grid.undo().is_some() {}
+        grid
+    }
+
+    /// Every position of the game, from the start to the current one.
+    pub fn replay(&self) -> Vec<Self> {
+        let mut grid = self.start();
+        let mut positions = vec![grid.clone()];
+        for &player_move in &self.moves {
+            grid.apply(player_move);
+            positions.push(grid.clone());
+        }
+        positions
+    }
+
+    /// Writes down every move as the first letter of the player and the column, such as `c3` for a cookie in the
+    /// third column. Without gravity, the row follows, as in `c3@2`, and tiles popped out are prefixed with a minus,
+    /// as in `-c3`.
+    pub fn move_notations(&self) -> Vec<String> {
+        self.history()
+            .iter()
+            .map(|placement| {
+                let player = &placement.player.name()[..1];
+                match (placement.action, self.config.variant) {
+                    (Action::Pop, _) => format!("-{}{}", player, placement.column),
+                    (Action::Place, Variant::Free) => format!("{}{}@{}", player, placement.column, placement.row),
+                    (Action::Place, _) => format!("{}{}", player, placement.column),
                 }
+            })
+            .collect()
+    }
+
+    /// Plays the moves written down by `move_notations` again, starting from the position in the form returned by
+    /// `fen`.
+    pub fn from_notations(start: &str, moves: &[String]) -> Result<Self, ()> {
+        let mut grid = Self::from_fen(start)?;
+        for notation in moves {
+            let (popped, notation) = match notation.strip_prefix('-') {
+                Some(notation) => (true, notation),
+                None => (false, notation.as_str()),
+            };
+            let player = match notation.get(..1) {
+                Some("c") => Player::Cookie,
+                Some("m") => Player::Milk,
+                _ => return Err(()),
+            };
+            let (column, row) = match notation[1..].split_once('@') {
+                Some((column, row)) => (column, Some(row)),
+                None => (&notation[1..], None),
+            };
+            let column = column.parse::<usize>().map_err(|_| ())?.checked_sub(1).ok_or(())?;
+            if column >= grid.config.cols {
+                return Err(());
             }
+            let player_move = match row {
+                _ if popped => {
+                    let bottom = (grid.config.rows - 1) * grid.config.cols + column;
+                    if grid.grid[bottom] != TileType::from(&player) {
+                        return Err(());
+                    }
+                    Move::Pop(player, column)
+                }
+                Some(row) => {
+                    let row = row.parse::<usize>().map_err(|_| ())?.checked_sub(1).ok_or(())?;
+                    let position = row * grid.config.cols + column;
+                    if row >= grid.config.rows || grid.grid[position] != TileType::Empty {
+                        return Err(());
+                    }
+                    Move::Place(player, position)
+                }
+                None => {
+                    let row = (0..grid.config.rows)
+                        .rev()
+                        .find(|row| grid.grid[row * grid.config.cols + column] == TileType::Empty)
+                        .ok_or(())?;
+                    Move::Place(player, row * grid.config.cols + column)
+                }
+            };
+            grid.apply(player_move);
+        }
+        Ok(grid)
+    }
 
-            let diagonal_slot_1 = &self.grid[0];
-            let diagonal_slot_2 = &self.grid[5];
-            let diagonal_slot_3 = &self.grid[10];
-            let diagonal_slot_4 = &self.grid[15];
-            if let Ok(player) = Player::try_from(diagonal_slot_1) {
-                if diagonal_slot_1 == diagonal_slot_2 && diagonal_slot_1 == diagonal_slot_3 && diagonal_slot_1 == diagonal_slot_4 {
-                    return GameState::Win(player);
+    /// Takes back the last move, returning it.
+    pub fn undo(&mut self) -> Option<Placement> {
+        let player_move = self.moves.pop()?;
+        match player_move {
+            Move::Place(_, position) => {
+                self.grid[position] = TileType::Empty;
+                self.empty_slots_left += 1;
+            }
+            Move::Pop(player, column) => {
+                for row in 0..self.config.rows - 1 {
+                    self.grid[row * self.config.cols + column] = self.grid[(row + 1) * self.config.cols + column];
                 }
+                self.grid[(self.config.rows - 1) * self.config.cols + column] = TileType::from(&player);
+                self.empty_slots_left -= 1;
             }
+        }
+        Some(self.describe(&player_move))
+    }
 
+    pub fn view(&self) -> BoardView {
+        BoardView {
+            rows: self.config.rows,
+            cols: self.config.cols,
+            win_length: self.config.win_length,
+            variant: self.config.variant,
+            cells: self.grid.chunks_exact(self.config.cols).map(<[TileType]>::to_vec).collect(),
+            last_move: self.last_move(),
+            empty_slots_left: self.empty_slots_left,
+            state: self.check_winner(),
+            fen: self.fen(),
+        }
+    }
 
-            let off_diagonal_1 = &self.grid[3];
-            let off_diagonal_2 = &self.grid[6];
-            let off_diagonal_3 = &self.grid[9];
-            let off_diagonal_4 = &self.grid[12];
-            if let Ok(player) = Player::try_from(off_diagonal_1) {
-                if off_diagonal_1 == off_diagonal_2 && off_diagonal_1 == off_diagonal_3 && off_diagonal_1 == off_diagonal_4 {
-                    return GameState::Win(player);
+    /// Compact textual form of the grid, similar to the chess FEN notation: rows from top to bottom separated by `/`,
+    /// with `c` for cookies, `m` for milk and digits for runs of empty slots, followed by the win length and by the
+    /// variant unless it's the classic one.
+    /// For example, `4/4/4/cm2 4` is a 4x4 connect-4 grid with a cookie and a milk in the bottom row.
+    pub fn fen(&self) -> String {
+        let rows: Vec<String> = self
+            .grid
+            .chunks_exact(self.config.cols)
+            .map(|row| {
+                let mut fen_row = String::new();
+                let mut empty_run = 0;
+                for tile in row {
+                    if let TileType::Empty = tile {
+                        empty_run += 1;
+                        continue;
+                    }
+                    if empty_run > 0 {
+                        fen_row.push_str(&empty_run.to_string());
+                        empty_run = 0;
+                    }
+                    fen_row.push(if let TileType::Cookie = tile { 'c' } else { 'm' });
+                }
+                if empty_run > 0 {
+                    fen_row.push_str(&empty_run.to_string());
                 }
+                fen_row
+            })
+            .collect();
+        match self.config.variant {
+            Variant::Classic => format!("{} {}", rows.join("/"), self.config.win_length),
+            variant => format!("{} {} {}", rows.join("/"), self.config.win_length, variant.name()),
+        }
+    }
+
+    /// Parses the form produced by `fen`. Unless the variant places tiles without gravity, the position must be
+    /// reachable by dropping tiles, so no tile can float above an empty slot.
+    pub fn from_fen(fen: &str) -> Result<Self, ()> {
+        let mut fields = fen.split_whitespace();
+        let rows = fields.next().ok_or(())?;
+        let win_length = fields.next().ok_or(())?.parse().map_err(|_| ())?;
+        let variant = fields.next().map_or(Ok(Variant::Classic), Variant::try_from)?;
+        if fields.next().is_some() {
+            return Err(());
+        }
+        let mut grid = Vec::new();
+        let mut cols = None;
+        for fen_row in rows.split('/') {
+            let mut row = Vec::new();
+            let mut chars = fen_row.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    'c' => row.push(TileType::Cookie),
+                    'm' => row.push(TileType::Milk),
+                    '0'..='9' => {
+                        let mut empty_run = c.to_digit(10).ok_or(())? as usize;
+                        while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                            empty_run = empty_run * 10 + digit as usize;
+                            chars.next();
+                            if empty_run > MAX_GRID_SIZE {
+                                return Err(());
+                            }
+                        }
+                        row.resize(row.len() + empty_run, TileType::Empty);
+                    }
+                    _ => return Err(()),
+                }
+                if row.len() > MAX_GRID_SIZE {
+                    return Err(());
+                }
+            }
+            if *cols.get_or_insert(row.len()) != row.len() {
+                return Err(());
             }
+            grid.extend(row);
+        }
+        let config = GridConfig {
+            rows: rows.split('/').count(),
+            cols: cols.ok_or(())?,
+            win_length,
+            variant,
+        };
+        if !config.is_valid() {
+            return Err(());
         }
-        if self.empty_slots_left == 0 {
+        let floating_tile = (config.cols..grid.len())
+            .any(|position| grid[position] == TileType::Empty && grid[position - config.cols] != TileType::Empty);
+        if floating_tile && variant != Variant::Free {
+            return Err(());
+        }
+        let empty_slots_left = grid.iter().filter(|&&tile| tile == TileType::Empty).count();
+        Ok(Self {
+            config,
+            grid,
+            moves: Vec::new(),
+            empty_slots_left,
+        })
+    }
+
+    pub fn tiles(&self) -> &[TileType] {
+        &self.grid
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.empty_slots_left == 0
+    }
+
+    /// Returns the positions making up a line of `win_length` slots that starts at the given slot and follows the
+    /// given direction, if it fits in the grid.
+    fn window_from(&self, row: usize, col: usize, (row_step, col_step): (isize, isize)) -> Option<Vec<usize>> {
+        (0..self.config.win_length as isize)
+            .map(|step| {
+                let row = row.checked_add_signed(row_step * step).filter(|&row| row < self.config.rows)?;
+                let col = col.checked_add_signed(col_step * step).filter(|&col| col < self.config.cols)?;
+                Some(row * self.config.cols + col)
+            })
+            .collect()
+    }
+
+    /// Returns every line of `win_length` slots that fits in the grid, following rows, columns and diagonals in this
+    /// order.
+    pub fn windows(&self) -> impl Iterator<Item = Vec<usize>> + '_ {
+        DIRECTIONS.into_iter().flat_map(move |direction| {
+            (0..self.config.rows).flat_map(move |row| {
+                (0..self.config.cols).filter_map(move |col| self.window_from(row, col, direction))
+            })
+        })
+    }
+
+    /// Returns the first line entirely made up of the tiles of a single player, along with that player.
+    /// Popping a tile out can complete lines of both players at once, in which case the player who moved wins.
+    pub fn winning_line(&self) -> Option<(Player, Vec<usize>)> {
+        let mut lines = self.windows().filter_map(|line| {
+            let first_slot = &self.grid[line[0]];
+            let player = Player::try_from(first_slot).ok()?;
+            line.iter()
+                .all(|&position| &self.grid[position] == first_slot)
+                .then_some((player, line))
+        });
+        let first_line = lines.next()?;
+        match self.last_mover() {
+            Some(mover) if mover != first_line.0 => Some(lines.find(|(player, _)| *player == mover).unwrap_or(first_line)),
+            _ => Some(first_line),
+        }
+    }
+
+    /// Scans rows, columns and diagonals in this order, returning the first full line found.
+    /// The whole grid is checked since a random board can contain lines that were never "placed".
+    /// A full grid is only a draw once the player to move can't play anymore, since tiles can still be popped out of
+    /// it in the pop-out variant.
+    pub fn check_winner(&self) -> GameState {
+        if let Some((player, line)) = self.winning_line() {
+            tracing::info!("Found winning line {:?} for {:?}", line, player);
+            return GameState::Win(player);
+        }
+        if self.is_full() && !self.can_move() {
             GameState::NoWin
         } else {
             GameState::Pending
         }
     }
 
-    pub fn place(&mut self, tile: TileType, column: usize) -> Result<(), ()> {
-        for depth in (column..=column + 12).rev().step_by(4) {
+    /// Whether the player to move has a legal move left. Without any move made yet, that player isn't known, so
+    /// either player having one is enough.
+    fn can_move(&self) -> bool {
+        match self.last_mover() {
+            Some(mover) => !self.legal_moves(mover.opponent()).is_empty(),
+            None => [Player::Cookie, Player::Milk].iter().any(|&player| !self.legal_moves(player).is_empty()),
+        }
+    }
+
+    /// Returns every move the rules variant allows the player: drops in the columns that still have room, or
+    /// placements in every empty slot without gravity, along with the tiles of the player that can be popped out.
+    pub fn legal_moves(&self, player: Player) -> Vec<Move> {
+        let (rows, cols) = (self.config.rows, self.config.cols);
+        let empty = |&position: &usize| self.grid[position] == TileType::Empty;
+        let mut moves: Vec<Move> = match self.config.variant {
+            Variant::Free => (0..rows * cols).filter(empty).map(|position| Move::Place(player, position)).collect(),
+            Variant::Classic | Variant::PopOut => (0..cols)
+                .filter_map(|column| (0..rows).rev().map(|row| row * cols + column).find(empty))
+                .map(|position| Move::Place(player, position))
+                .collect(),
+        };
+        if self.config.variant == Variant::PopOut {
+            let own = TileType::from(&player);
+            let bottom_row = (0..cols).filter(|&column| self.grid[(rows - 1) * cols + column] == own);
+            moves.extend(bottom_row.map(|column| Move::Pop(player, column)));
+        }
+        moves
+    }
+
+    /// Returns a copy of the grid with the given legal move played.
+    /// Unlike `place` and `pop`, nothing is logged, since this is used to explore moves.
+    pub fn with_move(&self, player_move: Move) -> Self {
+        let mut grid = self.clone();
+        grid.apply(player_move);
+        grid
+    }
+
+    /// Plays a move picked among the legal ones, such as the move of the computer player, checking it's still legal.
+    pub fn play(&mut self, player_move: Move) -> Result<(), PlaceError> {
+        let player = match player_move {
+            Move::Place(player, _) | Move::Pop(player, _) => player,
+        };
+        if !self.legal_moves(player).contains(&player_move) {
+            tracing::info!("Illegal move {:?}", player_move);
+            return Err(PlaceError::IllegalMove);
+        }
+        self.apply(player_move);
+        Ok(())
+    }
+
+    /// Drops a tile of the player in the given column, returning the position where it landed.
+    pub fn place(&mut self, player: Player, column: usize) -> Result<usize, PlaceError> {
+        if column >= self.config.cols {
+            return Err(PlaceError::InvalidColumn);
+        }
+        for row in (0..self.config.rows).rev() {
+            let depth = row * self.config.cols + column;
             tracing::info!("Checking tile {:#?}", self.grid[depth]);
             if let TileType::Empty = self.grid[depth] {
                 tracing::info!("Found available spot for column {} at depth {}", column, depth);
-                self.grid[depth] = tile;
-                self.last_placed = Some(depth);
-                self.empty_slots_left -= 1;
-                return Ok(());
+                self.apply(Move::Place(player, depth));
+                return Ok(depth);
             }
         }
         tracing::info!("No available spot for column {}", column);
-        Err(())
+        Err(PlaceError::ColumnFull)
     }
-}
\ No newline at end of file
+
+    /// Places a tile of the player in the given slot, ignoring gravity, returning its position.
+    pub fn place_at(&mut self, player: Player, row: usize, column: usize) -> Result<usize, PlaceError> {
+        if column >= self.config.cols {
+            return Err(PlaceError::InvalidColumn);
+        }
+        if row >= self.config.rows {
+            return Err(PlaceError::InvalidRow);
+        }
+        let position = row * self.config.cols + column;
+        if self.grid[position] != TileType::Empty {
+            tracing::info!("Slot {} already taken", position);
+            return Err(PlaceError::SlotTaken);
+        }
+        self.apply(Move::Place(player, position));
+        Ok(position)
+    }
+
+    /// Pops the bottom tile of the given column out, if it belongs to the player. The tiles above it fall down.
+    pub fn pop(&mut self, player: Player, column: usize) -> Result<(), PlaceError> {
+        if column >= self.config.cols {
+            return Err(PlaceError::InvalidColumn);
+        }
+        let bottom = self.grid[(self.config.rows - 1) * self.config.cols + column];
+        if bottom != TileType::from(&player) {
+            tracing::info!("Bottom tile of column {} is {:?}", column, bottom);
+            return Err(PlaceError::NotOwnTile);
+        }
+        self.apply(Move::Pop(player, column));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_popout_grid_is_pending_while_the_player_to_move_can_pop() {
+        let mut grid = Grid::from_fen("1mm/ccc/mmm/ccc 4 popout").unwrap();
+        grid.place(Player::Milk, 0).unwrap();
+        assert!(grid.is_full());
+        assert!(matches!(grid.check_winner(), GameState::Pending));
+        assert!(grid.pop(Player::Cookie, 0).is_ok());
+    }
+
+    #[test]
+    fn full_popout_grid_is_a_draw_once_the_player_to_move_cant_pop() {
+        let mut grid = Grid::from_fen("1mm/ccc/mmm/ccc 4 popout").unwrap();
+        grid.place(Player::Cookie, 0).unwrap();
+        assert!(grid.legal_moves(Player::Milk).is_empty());
+        assert!(matches!(grid.check_winner(), GameState::NoWin));
+    }
+
+    #[test]
+    fn legal_moves_follow_the_variant() {
+        let popout = Grid::from_fen("3/3/cm1 3 popout").unwrap();
+        assert_eq!(
+            popout.legal_moves(Player::Cookie),
+            vec![
+                Move::Place(Player::Cookie, 3),
+                Move::Place(Player::Cookie, 4),
+                Move::Place(Player::Cookie, 8),
+                Move::Pop(Player::Cookie, 0),
+            ]
+        );
+        let free = Grid::from_fen("c2/3/1m1 3 free").unwrap();
+        assert_eq!(free.legal_moves(Player::Milk).len(), 7);
+    }
+}
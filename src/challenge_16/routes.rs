@@ -2,10 +2,14 @@ use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::IntoResponse;
 use axum::Json;
 use headers::{Cookie, HeaderMapExt};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+/// Santa's public key, used to verify the tokens handed to the decode endpoint.
+const SANTA_PUBLIC_KEY: &[u8] = include_bytes!("../../assets/day16_santa_public_key.pem");
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
     sub: String,
@@ -35,7 +39,63 @@ pub(crate) async fn unwrap(header_map: HeaderMap) -> impl IntoResponse {
     let mut validation = Validation::default();
     validation.required_spec_claims.remove("exp");
     validation.validate_exp = false;
-    let gift = decode::<Claims>(&gift, &DecodingKey::from_secret("secret".as_ref()), &validation).unwrap();
+    let gift = match decode::<Claims>(gift, &DecodingKey::from_secret("secret".as_ref()), &validation) {
+        Ok(gift) => gift,
+        Err(e) => {
+            tracing::info!("Invalid gift: {:?}", e);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
     tracing::info!("Gift: {:?}", gift.claims.value);
-    Json(serde_json::from_str::<Value>(&gift.claims.value).unwrap()).into_response()
+    match serde_json::from_str::<Value>(&gift.claims.value) {
+        Ok(value) => Json(value).into_response(),
+        Err(_) => StatusCode::BAD_REQUEST.into_response(),
+    }
+}
+
+/// Decodes a JWT signed by Santa with either RS256 or RS512, returning its claims.
+/// Malformed tokens result in a 400 Bad Request, whereas tokens with an invalid signature result in a 401 Unauthorized.
+pub(crate) async fn decode_santa_token(body: String) -> impl IntoResponse {
+    tracing::info!("Decode called with payload {:?}", body);
+    let token = body.trim();
+    let header = match decode_header(token) {
+        Ok(header) => header,
+        Err(e) => {
+            tracing::info!("Invalid token header: {:?}", e);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+    if !matches!(header.alg, Algorithm::RS256 | Algorithm::RS512) {
+        tracing::info!("Unsupported algorithm: {:?}", header.alg);
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let key = match DecodingKey::from_rsa_pem(SANTA_PUBLIC_KEY) {
+        Ok(key) => key,
+        Err(e) => {
+            tracing::error!("Invalid Santa public key: {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let mut validation = Validation::new(header.alg);
+    validation.algorithms = vec![Algorithm::RS256, Algorithm::RS512];
+    validation.required_spec_claims.clear();
+    validation.validate_exp = false;
+    // Santa's tokens may name any audience, which no claim of ours is checked against
+    validation.validate_aud = false;
+    match decode::<Value>(token, &key, &validation) {
+        Ok(data) => {
+            tracing::info!("Claims: {:?}", data.claims);
+            Json(data.claims).into_response()
+        }
+        Err(e) => match e.kind() {
+            ErrorKind::InvalidSignature => {
+                tracing::info!("Invalid signature");
+                StatusCode::UNAUTHORIZED.into_response()
+            }
+            _ => {
+                tracing::info!("Invalid token: {:?}", e);
+                StatusCode::BAD_REQUEST.into_response()
+            }
+        },
+    }
 }
@@ -1 +1,3 @@
-pub(crate) mod routes;
\ No newline at end of file
+pub(crate) mod routes;
+pub(crate) mod structs;
+pub(crate) mod tasks;
\ No newline at end of file
@@ -1,15 +1,26 @@
 use crate::AppState;
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use async_stream::try_stream;
+use axum::body::Body;
+use axum::BoxError;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::IntoResponse;
 use axum::Json;
 use chrono::{DateTime, Utc};
+use futures::{Stream, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use sqlx::Row;
+use sqlx::postgres::PgRow;
+use sqlx::{Acquire, Executor, PgConnection, PgPool, Postgres, Row};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+/// Amount of quotes returned for each page by the list endpoint.
+const QUOTES_PER_PAGE: i64 = 3;
+/// Default and maximum amount of results returned for each page by the search endpoint.
+const SEARCH_RESULTS_PER_PAGE: i64 = 10;
+const MAX_SEARCH_RESULTS_PER_PAGE: i64 = 50;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct Quote {
     id: Uuid,
@@ -19,11 +30,24 @@ pub(crate) struct Quote {
     version: i32,
 }
 
+impl From<&PgRow> for Quote {
+    fn from(row: &PgRow) -> Self {
+        Self {
+            id: row.get("id"),
+            author: row.get("author"),
+            quote: row.get("quote"),
+            created_at: row.get("created_at"),
+            version: row.get("version"),
+        }
+    }
+}
+
 #[axum::debug_handler]
 pub(crate) async fn reset_quotes(State(state): State<Arc<RwLock<AppState>>>) -> impl IntoResponse {
     tracing::info!("Resetting quotes");
     let mut locked_state = state.write().await;
-    if let Err(e) = sqlx::query("TRUNCATE quotes").execute(&locked_state.pool).await {
+    locked_state.page_tokens.clear();
+    if let Err(e) = sqlx::query("TRUNCATE quotes CASCADE").execute(&locked_state.pool).await {
         tracing::info!("Error while resetting quotes: {:#?}", e);
         StatusCode::INTERNAL_SERVER_ERROR.into_response()
     } else {
@@ -32,54 +56,149 @@ pub(crate) async fn reset_quotes(State(state): State<Arc<RwLock<AppState>>>) ->
     }
 }
 
+/// Builds a strong entity tag out of the version of a quote.
+fn quote_etag(quote: &Quote) -> String {
+    format!("\"{}\"", quote.version)
+}
+
+/// Parses the If-Match header into the list of quote versions the caller expects.
+/// Returns None when the header is missing or is a wildcard, meaning any version is acceptable.
+/// Weak or malformed tags never match, since If-Match requires a strong comparison.
+fn expected_versions(headers: &HeaderMap) -> Result<Option<Vec<i32>>, StatusCode> {
+    let if_match = match headers.get(header::IF_MATCH) {
+        Some(value) => value.to_str().map_err(|_| StatusCode::BAD_REQUEST)?.trim(),
+        None => return Ok(None),
+    };
+    if if_match == "*" {
+        return Ok(None);
+    }
+    Ok(Some(
+        if_match
+            .split(',')
+            .filter_map(|tag| tag.trim().strip_prefix('"')?.strip_suffix('"')?.parse().ok())
+            .collect(),
+    ))
+}
+
 pub(crate) async fn get_quote(
     Path(id): Path<Uuid>,
     State(state): State<Arc<RwLock<AppState>>>,
-) -> Result<Json<Quote>, StatusCode> {
+) -> Result<impl IntoResponse, StatusCode> {
     tracing::info!("Getting quote {}", id);
     let pool = &state.read().await.pool;
-    let row = sqlx::query("SELECT id, author, quote, created_at, version FROM quotes WHERE id = $1")
+    let row = sqlx::query("SELECT id, author, quote, created_at, version, deleted_at FROM quotes WHERE id = $1")
         .bind(id)
         .fetch_optional(pool)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     match row {
-        Some(row) => Ok(Json(Quote {
-            id: row.get("id"),
-            author: row.get("author"),
-            quote: row.get("quote"),
-            created_at: row.get("created_at"),
-            version: row.get("version"),
-        })),
+        Some(row) if row.get::<Option<DateTime<Utc>>, _>("deleted_at").is_some() => Err(StatusCode::GONE),
+        Some(row) => {
+            let quote = Quote::from(&row);
+            Ok(([(header::ETAG, quote_etag(&quote))], Json(quote)))
+        }
         None => Err(StatusCode::NOT_FOUND),
     }
 }
 
+/// Explains why a query that only matches live quotes at the expected version found nothing: the quote either never
+/// existed, was soft deleted, or has a different version than the one the caller expected.
+async fn missing_quote_status<'e, E: Executor<'e, Database = Postgres>>(executor: E, id: Uuid) -> StatusCode {
+    match sqlx::query("SELECT deleted_at FROM quotes WHERE id = $1")
+        .bind(id)
+        .fetch_optional(executor)
+        .await
+    {
+        Ok(Some(row)) if row.get::<Option<DateTime<Utc>>, _>("deleted_at").is_some() => StatusCode::GONE,
+        Ok(Some(_)) => StatusCode::PRECONDITION_FAILED,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 pub(crate) async fn delete_quote(
     Path(id): Path<Uuid>,
     State(state): State<Arc<RwLock<AppState>>>,
+    headers: HeaderMap,
 ) -> Result<Json<Quote>, StatusCode> {
     tracing::info!("Deleting quote {}",id);
+    let expected_versions = expected_versions(&headers)?;
     let pool = &state.read().await.pool;
-    let row = sqlx::query("DELETE FROM quotes WHERE id = $1 RETURNING id, author, quote, created_at, version")
+    let row = sqlx::query(
+        "UPDATE quotes SET deleted_at = CURRENT_TIMESTAMP \
+         WHERE id = $1 AND deleted_at IS NULL AND ($2::INT[] IS NULL OR version = ANY($2)) \
+         RETURNING id, author, quote, created_at, version"
+    )
         .bind(id)
+        .bind(expected_versions)
         .fetch_optional(pool)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     match row {
-        Some(row) => Ok(Json(Quote {
-            id: row.get("id"),
+        Some(row) => Ok(Json(Quote::from(&row))),
+        None => Err(missing_quote_status(pool, id).await),
+    }
+}
+
+pub(crate) async fn restore_quote(
+    Path(id): Path<Uuid>,
+    State(state): State<Arc<RwLock<AppState>>>,
+) -> Result<Json<Quote>, StatusCode> {
+    tracing::info!("Restoring quote {}", id);
+    let pool = &state.read().await.pool;
+    let row = sqlx::query(
+        "UPDATE quotes SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL RETURNING id, author, quote, created_at, version"
+    )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    match row {
+        Some(row) => Ok(Json(Quote::from(&row))),
+        None => Err(match missing_quote_status(pool, id).await {
+            // The quote is live, so there's nothing to restore
+            StatusCode::PRECONDITION_FAILED => StatusCode::CONFLICT,
+            status => status,
+        }),
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct QuoteRevision {
+    quote_id: Uuid,
+    version: i32,
+    author: String,
+    quote: String,
+    revised_at: DateTime<Utc>,
+}
+
+impl From<&PgRow> for QuoteRevision {
+    fn from(row: &PgRow) -> Self {
+        Self {
+            quote_id: row.get("quote_id"),
+            version: row.get("version"),
             author: row.get("author"),
             quote: row.get("quote"),
-            created_at: row.get("created_at"),
-            version: row.get("version"),
-        })),
-        None => Err(StatusCode::NOT_FOUND),
+            revised_at: row.get("revised_at"),
+        }
     }
 }
 
+/// Stores the given version of a quote in its history.
+async fn record_revision(connection: &mut PgConnection, quote: &Quote) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO quote_revisions (quote_id, version, author, quote) VALUES ($1, $2, $3, $4)")
+        .bind(quote.id)
+        .bind(quote.version)
+        .bind(&quote.author)
+        .bind(&quote.quote)
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
 #[derive(Deserialize, Debug)]
 pub(crate) struct UpdateQuoteRequest {
     author: String,
@@ -89,30 +208,33 @@ pub(crate) struct UpdateQuoteRequest {
 pub(crate) async fn update_quote(
     Path(id): Path<Uuid>,
     State(state): State<Arc<RwLock<AppState>>>,
+    headers: HeaderMap,
     Json(payload): Json<UpdateQuoteRequest>,
-) -> Result<Json<Quote>, StatusCode> {
+) -> Result<impl IntoResponse, StatusCode> {
     tracing::info!("Updating quote {} with payload {:#?}", id, payload);
+    let expected_versions = expected_versions(&headers)?;
     let pool = &state.read().await.pool;
+    let mut tx = pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     let row = sqlx::query(
-        "UPDATE quotes SET author = $1, quote = $2, version = version + 1 WHERE id = $3 RETURNING id, author, quote, created_at, version"
+        "UPDATE quotes SET author = $1, quote = $2, version = version + 1 \
+         WHERE id = $3 AND deleted_at IS NULL AND ($4::INT[] IS NULL OR version = ANY($4)) \
+         RETURNING id, author, quote, created_at, version"
     )
         .bind(&payload.author)
         .bind(&payload.quote)
         .bind(id)
-        .fetch_optional(pool)
+        .bind(expected_versions)
+        .fetch_optional(&mut *tx)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    match row {
-        Some(row) => Ok(Json(Quote {
-            id: row.get("id"),
-            author: row.get("author"),
-            quote: row.get("quote"),
-            created_at: row.get("created_at"),
-            version: row.get("version"),
-        })),
-        None => Err(StatusCode::NOT_FOUND),
-    }
+    let quote = match row {
+        Some(row) => Quote::from(&row),
+        None => return Err(missing_quote_status(&mut *tx, id).await),
+    };
+    record_revision(&mut tx, &quote).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tx.commit().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(([(header::ETAG, quote_etag(&quote))], Json(quote)))
 }
 
 #[derive(Deserialize, Debug)]
@@ -128,24 +250,442 @@ pub(crate) async fn add_quote(
     tracing::info!("Adding quote with payload {:#?}", payload);
     let pool = &state.read().await.pool;
     let id = Uuid::new_v4();
+    let mut tx = pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     let row = sqlx::query(
         "INSERT INTO quotes (id, author, quote) VALUES ($1, $2, $3) RETURNING id, author, quote, created_at, version"
     )
         .bind(id)
         .bind(&payload.author)
         .bind(&payload.quote)
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
+    let quote = Quote::from(&row);
+    record_revision(&mut tx, &quote).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tx.commit().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     Ok((
         StatusCode::CREATED,
-        Json(Quote {
-            id: row.get("id"),
-            author: row.get("author"),
-            quote: row.get("quote"),
-            created_at: row.get("created_at"),
-            version: row.get("version"),
-        }),
+        Json(quote),
     ))
 }
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ListQuotesQuery {
+    token: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct QuotesPage {
+    quotes: Vec<Quote>,
+    page: i64,
+    next_token: Option<String>,
+}
+
+/// Lists the quotes a page at a time. The state is only locked for writing while the page token is looked up or
+/// issued, so that the query itself doesn't hold up the other requests.
+pub(crate) async fn list_quotes(
+    Query(query): Query<ListQuotesQuery>,
+    State(state): State<Arc<RwLock<AppState>>>,
+) -> Result<Json<QuotesPage>, StatusCode> {
+    tracing::info!("Listing quotes with query {:#?}", query);
+    let page = match query.token {
+        Some(token) => state.write().await.page_tokens.page(&token).ok_or_else(|| {
+            tracing::info!("Unknown or expired token {}", token);
+            StatusCode::BAD_REQUEST
+        })?,
+        None => 1,
+    };
+    let pool = state.read().await.pool.clone();
+    // One extra row is fetched to know whether a further page exists
+    let rows = sqlx::query(
+        "SELECT id, author, quote, created_at, version FROM quotes WHERE deleted_at IS NULL ORDER BY created_at, id LIMIT $1 OFFSET $2"
+    )
+        .bind(QUOTES_PER_PAGE + 1)
+        .bind((page - 1) * QUOTES_PER_PAGE)
+        .fetch_all(&pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let next_token = if rows.len() as i64 > QUOTES_PER_PAGE {
+        Some(state.write().await.page_tokens.issue(page + 1))
+    } else {
+        None
+    };
+    let quotes = rows
+        .iter()
+        .take(QUOTES_PER_PAGE as usize)
+        .map(Quote::from)
+        .collect();
+
+    Ok(Json(QuotesPage {
+        quotes,
+        page,
+        next_token,
+    }))
+}
+
+pub(crate) async fn get_quote_history(
+    Path(id): Path<Uuid>,
+    State(state): State<Arc<RwLock<AppState>>>,
+) -> Result<Json<Vec<QuoteRevision>>, StatusCode> {
+    tracing::info!("Getting history of quote {}", id);
+    let pool = &state.read().await.pool;
+    let rows = sqlx::query(
+        "SELECT quote_id, version, author, quote, revised_at FROM quote_revisions WHERE quote_id = $1 ORDER BY version"
+    )
+        .bind(id)
+        .fetch_all(pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if rows.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(rows.iter().map(QuoteRevision::from).collect()))
+}
+
+pub(crate) async fn get_quote_revision(
+    Path((id, version)): Path<(Uuid, i32)>,
+    State(state): State<Arc<RwLock<AppState>>>,
+) -> Result<Json<QuoteRevision>, StatusCode> {
+    tracing::info!("Getting version {} of quote {}", version, id);
+    let pool = &state.read().await.pool;
+    let row = sqlx::query(
+        "SELECT quote_id, version, author, quote, revised_at FROM quote_revisions WHERE quote_id = $1 AND version = $2"
+    )
+        .bind(id)
+        .bind(version)
+        .fetch_optional(pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    match row {
+        Some(row) => Ok(Json(QuoteRevision::from(&row))),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Restores the author and text of an earlier version, recording the result as a brand new version.
+pub(crate) async fn rollback_quote(
+    Path((id, version)): Path<(Uuid, i32)>,
+    State(state): State<Arc<RwLock<AppState>>>,
+) -> Result<Json<Quote>, StatusCode> {
+    tracing::info!("Rolling back quote {} to version {}", id, version);
+    let pool = &state.read().await.pool;
+    let mut tx = pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let row = sqlx::query(
+        "UPDATE quotes SET author = r.author, quote = r.quote, version = quotes.version + 1 \
+         FROM quote_revisions r \
+         WHERE quotes.id = $1 AND quotes.deleted_at IS NULL AND r.quote_id = quotes.id AND r.version = $2 \
+         RETURNING quotes.id, quotes.author, quotes.quote, quotes.created_at, quotes.version"
+    )
+        .bind(id)
+        .bind(version)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let quote = match row {
+        Some(row) => Quote::from(&row),
+        None => {
+            return Err(match missing_quote_status(&mut *tx, id).await {
+                // The quote is live, but never had the given version
+                StatusCode::PRECONDITION_FAILED => StatusCode::NOT_FOUND,
+                status => status,
+            })
+        }
+    };
+    record_revision(&mut tx, &quote).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tx.commit().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(quote))
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct SearchQuotesQuery {
+    q: String,
+    author: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct SearchHit {
+    quote: Quote,
+    rank: f32,
+    author_headline: String,
+    quote_headline: String,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct SearchResults {
+    results: Vec<SearchHit>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+/// Quotes matching a search, with the search query as `query`, shared by the queries of the hits and of their count.
+const SEARCH_MATCHES: &str = "FROM quotes, websearch_to_tsquery('english', $1) query \
+     WHERE deleted_at IS NULL AND search_vector @@ query \
+       AND ($2::TEXT IS NULL OR lower(author) = lower($2)) \
+       AND ($3::TIMESTAMPTZ IS NULL OR created_at >= $3) \
+       AND ($4::TIMESTAMPTZ IS NULL OR created_at < $4)";
+
+/// Searches quotes by author and text, ranking the hits and highlighting the matching words.
+/// The query follows the web search syntax, so "quoted text" is matched as a phrase and -word excludes a word.
+/// The total is counted separately from the page of hits, so that pages past the last one still report it.
+pub(crate) async fn search_quotes(
+    Query(query): Query<SearchQuotesQuery>,
+    State(state): State<Arc<RwLock<AppState>>>,
+) -> Result<Json<SearchResults>, StatusCode> {
+    tracing::info!("Searching quotes with query {:#?}", query);
+    if query.q.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(SEARCH_RESULTS_PER_PAGE);
+    if page < 1 || !(1..=MAX_SEARCH_RESULTS_PER_PAGE).contains(&per_page) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let pool = &state.read().await.pool;
+    let search_error = |e: sqlx::Error| {
+        tracing::info!("Error while searching quotes: {:#?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let hits_query = format!(
+        "SELECT id, author, quote, created_at, version, \
+                ts_rank(search_vector, query) AS rank, \
+                ts_headline('english', author, query) AS author_headline, \
+                ts_headline('english', quote, query) AS quote_headline \
+         {} \
+         ORDER BY rank DESC, created_at, id \
+         LIMIT $5 OFFSET $6",
+        SEARCH_MATCHES
+    );
+    let rows = sqlx::query(&hits_query)
+        .bind(&query.q)
+        .bind(&query.author)
+        .bind(query.from)
+        .bind(query.to)
+        .bind(per_page)
+        .bind((page - 1) * per_page)
+        .fetch_all(pool)
+        .await
+        .map_err(search_error)?;
+    let count_query = format!("SELECT COUNT(*) {}", SEARCH_MATCHES);
+    let total: i64 = sqlx::query_scalar(&count_query)
+        .bind(&query.q)
+        .bind(&query.author)
+        .bind(query.from)
+        .bind(query.to)
+        .fetch_one(pool)
+        .await
+        .map_err(search_error)?;
+
+    let results = rows
+        .iter()
+        .map(|row| SearchHit {
+            quote: Quote::from(row),
+            rank: row.get("rank"),
+            author_headline: row.get("author_headline"),
+            quote_headline: row.get("quote_headline"),
+        })
+        .collect();
+
+    Ok(Json(SearchResults {
+        results,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+/// Formats supported by the import and export endpoints.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum TransferFormat {
+    JsonLines,
+    Csv,
+}
+
+impl TransferFormat {
+    /// Plain `application/json` isn't supported, since JSON Lines aren't a valid JSON document.
+    fn from_mime_type(mime_type: &str) -> Option<Self> {
+        match mime_type.split(';').next().unwrap_or_default().trim() {
+            "application/jsonl" | "application/x-ndjson" => Some(Self::JsonLines),
+            "text/csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+
+    /// Picks the first supported format listed in the Accept header, defaulting to JSON Lines.
+    fn from_accept(headers: &HeaderMap) -> Option<Self> {
+        let accept = match headers.get(header::ACCEPT).and_then(|accept| accept.to_str().ok()) {
+            Some(accept) => accept,
+            None => return Some(Self::JsonLines),
+        };
+        accept.split(',').find_map(|mime_type| match mime_type.split(';').next().unwrap_or_default().trim() {
+            "*/*" | "application/*" => Some(Self::JsonLines),
+            "text/*" => Some(Self::Csv),
+            mime_type => Self::from_mime_type(mime_type),
+        })
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::JsonLines => "application/jsonl",
+            Self::Csv => "text/csv",
+        }
+    }
+}
+
+fn csv_line<T: Serialize>(record: T, with_headers: bool) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::WriterBuilder::new().has_headers(with_headers).from_writer(Vec::new());
+    writer.serialize(record)?;
+    writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+/// Serializes every live quote, ordered by creation date, one line at a time.
+fn quote_lines(pool: PgPool, format: TransferFormat) -> impl Stream<Item = Result<Vec<u8>, BoxError>> {
+    try_stream! {
+        let mut rows = sqlx::query(
+            "SELECT id, author, quote, created_at, version FROM quotes WHERE deleted_at IS NULL ORDER BY created_at, id"
+        )
+            .fetch(&pool);
+        let mut first = true;
+        while let Some(row) = rows.try_next().await? {
+            let quote = Quote::from(&row);
+            let line = match format {
+                TransferFormat::JsonLines => {
+                    let mut line = serde_json::to_vec(&quote)?;
+                    line.push(b'\n');
+                    line
+                }
+                TransferFormat::Csv => csv_line(&quote, first)?,
+            };
+            first = false;
+            yield line;
+        }
+    }
+}
+
+/// Streams every live quote as either JSON Lines or CSV, depending on the Accept header.
+pub(crate) async fn export_quotes(
+    State(state): State<Arc<RwLock<AppState>>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    let format = TransferFormat::from_accept(&headers).ok_or(StatusCode::NOT_ACCEPTABLE)?;
+    tracing::info!("Exporting quotes as {:?}", format);
+    let pool = state.read().await.pool.clone();
+    Ok(([(header::CONTENT_TYPE, format.content_type())], Body::from_stream(quote_lines(pool, format))))
+}
+
+#[derive(Deserialize, Debug)]
+struct ImportedQuote {
+    id: Option<Uuid>,
+    author: String,
+    quote: String,
+    created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct ImportError {
+    line: u64,
+    error: String,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct ImportReport {
+    imported: usize,
+    errors: Vec<ImportError>,
+}
+
+/// Imports quotes in JSON Lines or CSV format, depending on the Content-Type header, within a single transaction.
+/// Supplied ids and creation dates are kept. Rows that can't be parsed or inserted are reported and skipped, without
+/// affecting the other rows.
+pub(crate) async fn import_quotes(
+    State(state): State<Arc<RwLock<AppState>>>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<ImportReport>, StatusCode> {
+    let format = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(TransferFormat::from_mime_type)
+        .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+    tracing::info!("Importing quotes as {:?}", format);
+
+    let mut errors = Vec::new();
+    let mut parsed = Vec::new();
+    match format {
+        TransferFormat::JsonLines => {
+            for (index, line) in body.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<ImportedQuote>(line) {
+                    Ok(quote) => parsed.push((index as u64 + 1, quote)),
+                    Err(e) => errors.push(ImportError { line: index as u64 + 1, error: e.to_string() }),
+                }
+            }
+        }
+        TransferFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(body.as_bytes());
+            let csv_headers = reader.headers().map_err(|_| StatusCode::BAD_REQUEST)?.clone();
+            for record in reader.records() {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(e) => {
+                        let line = e.position().map(|position| position.line()).unwrap_or_default();
+                        errors.push(ImportError { line, error: e.to_string() });
+                        continue;
+                    }
+                };
+                let line = record.position().map(|position| position.line()).unwrap_or_default();
+                match record.deserialize::<ImportedQuote>(Some(&csv_headers)) {
+                    Ok(quote) => parsed.push((line, quote)),
+                    Err(e) => errors.push(ImportError { line, error: e.to_string() }),
+                }
+            }
+        }
+    }
+
+    let pool = &state.read().await.pool;
+    let mut tx = pool.begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut imported = 0;
+    for (line, payload) in parsed {
+        // Every row gets its own savepoint, so that a rejected row doesn't abort the whole import
+        let mut savepoint = (&mut *tx).begin().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let row = sqlx::query(
+            "INSERT INTO quotes (id, author, quote, created_at) VALUES ($1, $2, $3, COALESCE($4, CURRENT_TIMESTAMP)) \
+             RETURNING id, author, quote, created_at, version"
+        )
+            .bind(payload.id.unwrap_or_else(Uuid::new_v4))
+            .bind(&payload.author)
+            .bind(&payload.quote)
+            .bind(payload.created_at)
+            .fetch_one(&mut *savepoint)
+            .await;
+        let result = match row {
+            Ok(row) => record_revision(&mut savepoint, &Quote::from(&row)).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(_) => {
+                savepoint.commit().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                imported += 1;
+            }
+            Err(e) => {
+                savepoint.rollback().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                errors.push(ImportError { line, error: e.to_string() });
+            }
+        }
+    }
+    tx.commit().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    errors.sort_by_key(|error| error.line);
+    tracing::info!("Imported {} quotes, rejected {}", imported, errors.len());
+    Ok(Json(ImportReport { imported, errors }))
+}
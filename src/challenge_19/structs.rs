@@ -0,0 +1,51 @@
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Length of the opaque tokens handed out by the list endpoint.
+const TOKEN_LENGTH: usize = 16;
+
+/// Keeps track of the continuation tokens handed out by the list endpoint, mapping each token to the page it unlocks.
+/// Tokens are only valid for the configured TTL.
+#[derive(Debug)]
+pub(crate) struct PageTokens {
+    tokens: HashMap<String, (i64, Instant)>,
+    ttl: Duration,
+}
+
+impl PageTokens {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            tokens: HashMap::new(),
+            ttl,
+        }
+    }
+
+    /// Generates a new token pointing to the given page.
+    pub fn issue(&mut self, page: i64) -> String {
+        self.purge_expired();
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(TOKEN_LENGTH)
+            .map(char::from)
+            .collect();
+        self.tokens.insert(token.clone(), (page, Instant::now()));
+        token
+    }
+
+    /// Returns the page the token points to, if the token exists and hasn't expired yet.
+    pub fn page(&mut self, token: &str) -> Option<i64> {
+        self.purge_expired();
+        self.tokens.get(token).map(|(page, _)| *page)
+    }
+
+    pub fn clear(&mut self) {
+        self.tokens.clear();
+    }
+
+    fn purge_expired(&mut self) {
+        let ttl = self.ttl;
+        self.tokens.retain(|_, (_, issued_at)| issued_at.elapsed() < ttl);
+    }
+}
@@ -0,0 +1,18 @@
+use sqlx::PgPool;
+use std::time::Duration;
+
+/// Periodically hard deletes the quotes that have been soft deleted for longer than the retention period.
+pub(crate) async fn purge_deleted_quotes(pool: PgPool, retention: Duration, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let result = sqlx::query("DELETE FROM quotes WHERE deleted_at < CURRENT_TIMESTAMP - make_interval(secs => $1)")
+            .bind(retention.as_secs_f64())
+            .execute(&pool)
+            .await;
+        match result {
+            Ok(result) => tracing::info!("Purged {} deleted quotes", result.rows_affected()),
+            Err(e) => tracing::error!("Error while purging deleted quotes: {:#?}", e),
+        }
+    }
+}
@@ -1,2 +1,6 @@
+pub(crate) mod ciphers;
+pub(crate) mod families;
+pub(crate) mod problems;
+pub(crate) mod ranges;
 pub(crate) mod routes;
 pub(crate) mod structs;
\ No newline at end of file
@@ -0,0 +1,212 @@
+use crate::challenge_2::ranges::Address;
+use serde::Deserialize;
+
+/// Rounds of the Feistel network, enough for every bit of the address to depend on every bit of the key.
+const FEISTEL_ROUNDS: u64 = 8;
+
+/// A reversible way of turning an address into another one of the same family, given a key address.
+/// Addresses are handled as numbers of the given width in bits, so that any result is still a valid address.
+pub(crate) trait AddressCipher {
+    fn encrypt(&self, from: u128, key: u128, width: u32) -> u128;
+    fn decrypt(&self, to: u128, key: u128, width: u32) -> u128;
+
+    /// Finds the key turning `from` into `to`. By default, the key is deciphered from `to` as if `from` were the key,
+    /// which holds for the modes where both play the same role.
+    fn recover_key(&self, from: u128, to: u128, width: u32) -> Option<u128> {
+        Some(self.decrypt(to, from, width))
+    }
+
+    /// Whether keys can be recovered from an address and its encrypted form at all.
+    fn recovers_keys(&self) -> bool {
+        true
+    }
+}
+
+/// Cipher modes selectable through the `mode` query parameter.
+#[derive(Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CipherMode {
+    /// Adds the key to the address octet by octet, wrapping around, and subtracts it back.
+    Add,
+    Xor,
+    /// Rotates the bits of the address to the left by the key, taken as a number.
+    Rotate,
+    /// Keyed format-preserving encryption through a balanced Feistel network.
+    Feistel,
+}
+
+impl CipherMode {
+    pub fn cipher(&self) -> &'static dyn AddressCipher {
+        match self {
+            CipherMode::Add => &AddCipher,
+            CipherMode::Xor => &XorCipher,
+            CipherMode::Rotate => &RotateCipher,
+            CipherMode::Feistel => &FeistelCipher,
+        }
+    }
+
+    pub fn encrypt<A: Address>(&self, from: A, key: A) -> A {
+        A::from_bits(self.cipher().encrypt(from.to_bits(), key.to_bits(), A::BITS))
+    }
+
+    pub fn recover_key<A: Address>(&self, from: A, to: A) -> Option<A> {
+        self.cipher().recover_key(from.to_bits(), to.to_bits(), A::BITS).map(A::from_bits)
+    }
+}
+
+fn width_mask(width: u32) -> u128 {
+    u128::MAX >> (128 - width)
+}
+
+struct AddCipher;
+
+impl AddCipher {
+    fn map_octets(a: u128, b: u128, width: u32, op: fn(u8, u8) -> u8) -> u128 {
+        (0..width / 8)
+            .map(|i| i * 8)
+            .map(|shift| (op((a >> shift) as u8, (b >> shift) as u8) as u128) << shift)
+            .fold(0, |result, octet| result | octet)
+    }
+}
+
+impl AddressCipher for AddCipher {
+    fn encrypt(&self, from: u128, key: u128, width: u32) -> u128 {
+        Self::map_octets(from, key, width, u8::wrapping_add)
+    }
+
+    fn decrypt(&self, to: u128, key: u128, width: u32) -> u128 {
+        Self::map_octets(to, key, width, u8::wrapping_sub)
+    }
+}
+
+struct XorCipher;
+
+impl AddressCipher for XorCipher {
+    fn encrypt(&self, from: u128, key: u128, _width: u32) -> u128 {
+        from ^ key
+    }
+
+    fn decrypt(&self, to: u128, key: u128, _width: u32) -> u128 {
+        to ^ key
+    }
+}
+
+struct RotateCipher;
+
+impl RotateCipher {
+    fn rotate_left(bits: u128, by: u32, width: u32) -> u128 {
+        match by % width {
+            0 => bits,
+            by => ((bits << by) | (bits >> (width - by))) & width_mask(width),
+        }
+    }
+}
+
+impl AddressCipher for RotateCipher {
+    fn encrypt(&self, from: u128, key: u128, width: u32) -> u128 {
+        Self::rotate_left(from, (key % width as u128) as u32, width)
+    }
+
+    fn decrypt(&self, to: u128, key: u128, width: u32) -> u128 {
+        Self::rotate_left(to, width - (key % width as u128) as u32, width)
+    }
+
+    /// Looks for the smallest rotation turning `from` into `to`, if any.
+    fn recover_key(&self, from: u128, to: u128, width: u32) -> Option<u128> {
+        (0..width).find(|&by| Self::rotate_left(from, by, width) == to).map(u128::from)
+    }
+}
+
+struct FeistelCipher;
+
+impl FeistelCipher {
+    /// Finalizer of SplitMix64, spreading every bit of its input over its whole output.
+    fn mix(mut z: u64) -> u64 {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn round_key(key: u128, round: u64) -> u64 {
+        Self::mix(key as u64 ^ Self::mix((key >> 64) as u64 ^ round.wrapping_mul(0x9e3779b97f4a7c15)))
+    }
+
+    fn round(half: u128, key: u128, round: u64, half_mask: u128) -> u128 {
+        Self::mix(half as u64 ^ Self::round_key(key, round)) as u128 & half_mask
+    }
+}
+
+impl AddressCipher for FeistelCipher {
+    fn encrypt(&self, from: u128, key: u128, width: u32) -> u128 {
+        let half_mask = width_mask(width / 2);
+        let (mut left, mut right) = (from >> (width / 2), from & half_mask);
+        for round in 0..FEISTEL_ROUNDS {
+            (left, right) = (right, left ^ Self::round(right, key, round, half_mask));
+        }
+        (left << (width / 2)) | right
+    }
+
+    fn decrypt(&self, to: u128, key: u128, width: u32) -> u128 {
+        let half_mask = width_mask(width / 2);
+        let (mut left, mut right) = (to >> (width / 2), to & half_mask);
+        for round in (0..FEISTEL_ROUNDS).rev() {
+            (left, right) = (right ^ Self::round(left, key, round, half_mask), left);
+        }
+        (left << (width / 2)) | right
+    }
+
+    fn recover_key(&self, _from: u128, _to: u128, _width: u32) -> Option<u128> {
+        None
+    }
+
+    fn recovers_keys(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const MODES: [CipherMode; 4] = [CipherMode::Add, CipherMode::Xor, CipherMode::Rotate, CipherMode::Feistel];
+    const SAMPLES: usize = 10_000;
+
+    fn check_round_trips<A: Address + PartialEq + std::fmt::Debug>(random: impl Fn(&mut StdRng) -> A) {
+        let mut rng = StdRng::seed_from_u64(2);
+        for mode in MODES {
+            let cipher = mode.cipher();
+            for _ in 0..SAMPLES {
+                let (from, key) = (random(&mut rng), random(&mut rng));
+                let to = mode.encrypt(from, key);
+                let decrypted = A::from_bits(cipher.decrypt(to.to_bits(), key.to_bits(), A::BITS));
+                assert_eq!(decrypted, from, "{:?} doesn't decrypt {} back with key {}", mode, to, key);
+                if let Some(recovered) = mode.recover_key(from, to) {
+                    assert_eq!(mode.encrypt(from, recovered), to, "{:?} recovered a wrong key {}", mode, recovered);
+                } else {
+                    assert!(!cipher.recovers_keys(), "{:?} didn't recover a key", mode);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ipv4_modes_invert() {
+        check_round_trips(|rng| Ipv4Addr::from(rng.gen::<u32>()));
+    }
+
+    #[test]
+    fn ipv6_modes_invert() {
+        check_round_trips(|rng| Ipv6Addr::from(rng.gen::<u128>()));
+    }
+
+    #[test]
+    fn add_and_xor_match_the_original_ciphers() {
+        let (from, key) = ("10.0.0.0".parse::<Ipv4Addr>().unwrap(), "1.2.3.255".parse().unwrap());
+        assert_eq!(CipherMode::Add.encrypt(from, key), "11.2.3.255".parse::<Ipv4Addr>().unwrap());
+        let (from, key) = ("fe80::1".parse::<Ipv6Addr>().unwrap(), "5:6:7::3333".parse().unwrap());
+        assert_eq!(CipherMode::Xor.encrypt(from, key), "fe85:6:7::3332".parse::<Ipv6Addr>().unwrap());
+    }
+}
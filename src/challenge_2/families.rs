@@ -0,0 +1,90 @@
+use serde::{de, Deserialize, Deserializer};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Prefix of the IPv6 addresses embedding an IPv4 address in their last 32 bits for NAT64, from RFC 6052.
+const NAT64_PREFIX: [u16; 6] = [0x64, 0xff9b, 0, 0, 0, 0];
+/// First 16 bits of the 6to4 addresses, which embed an IPv4 address in the following 32 bits. Only the addresses of
+/// the 6to4 routers themselves, whose remaining 80 bits are zero, are taken for the IPv4 address they embed.
+const SIX_TO_FOUR_PREFIX: u16 = 0x2002;
+
+/// Ways an IPv4 address can be written as an IPv6 one.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Embedding {
+    /// `::ffff:1.2.3.4`
+    Mapped,
+    /// `2002:102:304::`
+    SixToFour,
+    /// `64:ff9b::1.2.3.4`
+    Nat64,
+}
+
+/// Finds the IPv4 address embedded in an IPv6 one, along with how it was embedded.
+pub(crate) fn embedded_ipv4(address: Ipv6Addr) -> Option<(Ipv4Addr, Embedding)> {
+    let segments = address.segments();
+    let ipv4 = |high: u16, low: u16| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+    if let Some(mapped) = address.to_ipv4_mapped() {
+        Some((mapped, Embedding::Mapped))
+    } else if segments[..6] == NAT64_PREFIX {
+        Some((ipv4(segments[6], segments[7]), Embedding::Nat64))
+    } else if segments[0] == SIX_TO_FOUR_PREFIX && segments[3..] == [0; 5] {
+        Some((ipv4(segments[1], segments[2]), Embedding::SixToFour))
+    } else {
+        None
+    }
+}
+
+/// Normalizes an IPv6 address embedding an IPv4 one to that IPv4 address.
+pub(crate) fn normalize_address(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(ipv6) => match embedded_ipv4(ipv6) {
+            Some((ipv4, embedding)) => {
+                tracing::info!("Normalized {:?} address {} to {}", embedding, ipv6, ipv4);
+                IpAddr::V4(ipv4)
+            }
+            None => address,
+        },
+        ipv4 => ipv4,
+    }
+}
+
+/// Normalizes both addresses ciphered together, so that the cipher is picked from the family of the normalized pair.
+/// When only one of two IPv6 addresses embeds an IPv4 one, both keep the IPv6 cipher instead.
+pub(crate) fn normalize_pair(from: IpAddr, other: IpAddr) -> (IpAddr, IpAddr) {
+    match (normalize_address(from), normalize_address(other)) {
+        (IpAddr::V4(_), IpAddr::V6(_)) | (IpAddr::V6(_), IpAddr::V4(_)) if from.is_ipv6() && other.is_ipv6() => {
+            (from, other)
+        }
+        normalized => normalized,
+    }
+}
+
+pub(crate) fn deserialize_address<'de, D: Deserializer<'de>>(deserializer: D) -> Result<IpAddr, D::Error> {
+    let address = String::deserialize(deserializer)?;
+    address.trim().parse().map_err(|_| de::Error::custom(format!("invalid address: {}", address)))
+}
+
+/// How the resulting addresses are written, chosen through the `format` query parameter.
+#[derive(Deserialize, Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OutputFormat {
+    /// The shortest standard form of the address.
+    #[default]
+    Canonical,
+    /// IPv6 addresses with all of their eight groups of four digits. IPv4 addresses are written as usual.
+    Expanded,
+    /// IPv4 addresses as IPv4-mapped IPv6 addresses. IPv6 addresses are written as usual.
+    Mapped,
+}
+
+impl OutputFormat {
+    pub fn format(&self, address: IpAddr) -> String {
+        match (self, address) {
+            (OutputFormat::Expanded, IpAddr::V6(ipv6)) => ipv6
+                .segments()
+                .map(|segment| format!("{:04x}", segment))
+                .join(":"),
+            (OutputFormat::Mapped, IpAddr::V4(ipv4)) => format!("::ffff:{}", ipv4),
+            (_, address) => address.to_string(),
+        }
+    }
+}
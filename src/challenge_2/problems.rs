@@ -0,0 +1,98 @@
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_path_to_error::Segment;
+
+/// Type of the problems caused by a malformed or missing query parameter.
+const INVALID_PARAMETER_TYPE: &str = "/problems/invalid-query-parameter";
+
+/// Query parameters of a route, described in plain words for the clients getting them wrong.
+pub(crate) trait QueryParameters {
+    fn expected(parameter: &str) -> Option<&'static str>;
+}
+
+/// Problem details of a failed request, as described by RFC 7807, naming the query parameter at fault.
+/// They're boxed, so that results failing with them stay small.
+#[derive(Debug)]
+pub(crate) struct Problem(Box<ProblemDetails>);
+
+#[derive(Serialize, Debug)]
+struct ProblemDetails {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance: Option<String>,
+    parameter: Option<String>,
+    expected: Option<&'static str>,
+    /// The value sent for the parameter, missing if the parameter was.
+    received: Option<String>,
+}
+
+impl Problem {
+    pub fn invalid_parameter<Q: QueryParameters>(
+        parameter: Option<&str>,
+        received: Option<String>,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self(Box::new(ProblemDetails {
+            problem_type: INVALID_PARAMETER_TYPE,
+            title: "Invalid query parameter",
+            status: StatusCode::BAD_REQUEST.as_u16(),
+            detail: detail.into(),
+            instance: None,
+            parameter: parameter.map(str::to_string),
+            expected: parameter.and_then(Q::expected),
+            received,
+        }))
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        tracing::info!("Error: {:?}", self.0);
+        let status = StatusCode::from_u16(self.0.status).unwrap_or(StatusCode::BAD_REQUEST);
+        (status, [(header::CONTENT_TYPE, "application/problem+json")], Json(self.0)).into_response()
+    }
+}
+
+/// Extracts the query parameters like `Query`, but rejects malformed ones with problem details naming the parameter,
+/// what it expects and what it received, instead of a plain text message.
+#[derive(Debug)]
+pub(crate) struct ProblemQuery<T>(pub T);
+
+#[async_trait]
+impl<T: DeserializeOwned + QueryParameters, S: Send + Sync> FromRequestParts<S> for ProblemQuery<T> {
+    type Rejection = Problem;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer = serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        serde_path_to_error::deserialize(deserializer).map(ProblemQuery).map_err(|e| {
+            let detail = e.inner().to_string();
+            let parameter = match e.path().iter().next() {
+                Some(Segment::Map { key }) => Some(key.clone()),
+                // Missing parameters are only named by the message of the error
+                _ => detail
+                    .strip_prefix("missing field `")
+                    .and_then(|rest| rest.split('`').next())
+                    .map(str::to_string),
+            };
+            let received = parameter.as_ref().and_then(|parameter| {
+                form_urlencoded::parse(query.as_bytes())
+                    .find(|(name, _)| name == parameter)
+                    .map(|(_, value)| value.into_owned())
+            });
+            let mut problem = Problem::invalid_parameter::<T>(parameter.as_deref(), received, detail);
+            problem.0.instance = Some(parts.uri.to_string());
+            problem
+        })
+    }
+}
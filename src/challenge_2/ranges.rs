@@ -0,0 +1,171 @@
+use crate::challenge_2::families::{embedded_ipv4, normalize_address, Embedding};
+use serde::{de, Deserialize, Deserializer};
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// Most addresses of a range that are ciphered in a single request. Larger ranges are cut after that many addresses.
+pub(crate) const MAX_RANGE_SIZE: u128 = 1 << 16;
+
+/// An address that can be handled as a plain number, so that ranges of them can be walked through.
+pub(crate) trait Address: Copy + Display + Send + 'static {
+    const BITS: u32;
+
+    fn parse(address: &str) -> Option<Self>;
+    fn to_bits(self) -> u128;
+    fn from_bits(bits: u128) -> Self;
+}
+
+impl Address for Ipv4Addr {
+    const BITS: u32 = 32;
+
+    fn parse(address: &str) -> Option<Self> {
+        address.parse().ok()
+    }
+
+    fn to_bits(self) -> u128 {
+        u32::from(self).into()
+    }
+
+    fn from_bits(bits: u128) -> Self {
+        Ipv4Addr::from(bits as u32)
+    }
+}
+
+impl Address for Ipv6Addr {
+    const BITS: u32 = 128;
+
+    fn parse(address: &str) -> Option<Self> {
+        address.parse().ok()
+    }
+
+    fn to_bits(self) -> u128 {
+        self.into()
+    }
+
+    fn from_bits(bits: u128) -> Self {
+        Ipv6Addr::from(bits)
+    }
+}
+
+/// Consecutive addresses, given either as a single address, as a CIDR block such as `10.0.0.0/24`, or as an
+/// inclusive range such as `10.0.0.1-10.0.0.9`.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct AddressRange<A> {
+    first: A,
+    last: A,
+}
+
+impl<A: Address> AddressRange<A> {
+    pub fn is_single(&self) -> bool {
+        self.first.to_bits() == self.last.to_bits()
+    }
+
+    /// Whether the range holds more than `MAX_RANGE_SIZE` addresses, so that only the first ones are walked through.
+    pub fn is_capped(&self) -> bool {
+        self.last.to_bits() - self.first.to_bits() >= MAX_RANGE_SIZE
+    }
+
+    /// Walks through the addresses of the range in order, stopping after `MAX_RANGE_SIZE` of them.
+    pub fn addresses(&self) -> impl Iterator<Item = A> + Send + 'static {
+        (self.first.to_bits()..=self.last.to_bits())
+            .take(MAX_RANGE_SIZE as usize)
+            .map(A::from_bits)
+    }
+}
+
+impl<A: Address> FromStr for AddressRange<A> {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |address: &str| A::parse(address.trim()).ok_or(());
+        if let Some((address, prefix)) = s.split_once('/') {
+            let prefix: u32 = prefix.trim().parse().map_err(|_| ())?;
+            if prefix > A::BITS {
+                return Err(());
+            }
+            // Host bits are ignored, so that any address of the block can be given
+            let host_mask = (u128::MAX >> (128 - A::BITS)).checked_shr(prefix).unwrap_or(0);
+            let network = parse(address)?.to_bits() & !host_mask;
+            Ok(Self { first: A::from_bits(network), last: A::from_bits(network | host_mask) })
+        } else if let Some((first, last)) = s.split_once('-') {
+            let (first, last) = (parse(first)?, parse(last)?);
+            if first.to_bits() > last.to_bits() {
+                return Err(());
+            }
+            Ok(Self { first, last })
+        } else {
+            let address = parse(s)?;
+            Ok(Self { first: address, last: address })
+        }
+    }
+}
+
+impl<'de, A: Address> Deserialize<'de> for AddressRange<A> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let range = String::deserialize(deserializer)?;
+        range
+            .parse()
+            .map_err(|_| de::Error::custom(format!("invalid address, CIDR block or range: {}", range)))
+    }
+}
+
+impl AddressRange<Ipv6Addr> {
+    /// The range of the IPv4 addresses embedded in the addresses of the range, if they all embed one the same way.
+    /// Mapped and NAT64 addresses embed an IPv4 address in their last bits, so that the addresses between two of them
+    /// do as well, whereas the addresses between two 6to4 ones don't.
+    fn embedded_ipv4(&self) -> Option<AddressRange<Ipv4Addr>> {
+        let (first, first_embedding) = embedded_ipv4(self.first)?;
+        let (last, last_embedding) = embedded_ipv4(self.last)?;
+        let contiguous = first_embedding != Embedding::SixToFour || self.is_single();
+        (first_embedding == last_embedding && contiguous).then_some(AddressRange { first, last })
+    }
+}
+
+/// A range of addresses of either family.
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum AnyRange {
+    V4(AddressRange<Ipv4Addr>),
+    V6(AddressRange<Ipv6Addr>),
+}
+
+impl AnyRange {
+    /// Normalizes a range of IPv4 addresses embedded in IPv6 ones to a range of these IPv4 addresses.
+    fn normalized(self) -> Self {
+        match self {
+            AnyRange::V6(ipv6) => match ipv6.embedded_ipv4() {
+                Some(ipv4) => {
+                    tracing::info!("Normalized {:?} to IPv4 addresses {:?}", ipv6, ipv4);
+                    AnyRange::V4(ipv4)
+                }
+                None => self,
+            },
+            ipv4 => ipv4,
+        }
+    }
+
+    /// Normalizes the range and the address it's ciphered with, like `normalize_pair` does for single addresses.
+    pub fn normalize_with(self, other: IpAddr) -> (Self, IpAddr) {
+        match (self.normalized(), normalize_address(other)) {
+            (AnyRange::V4(_), IpAddr::V6(_)) | (AnyRange::V6(_), IpAddr::V4(_))
+                if matches!(self, AnyRange::V6(_)) && other.is_ipv6() =>
+            {
+                (self, other)
+            }
+            normalized => normalized,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for AnyRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let range = String::deserialize(deserializer)?;
+        if let Ok(ipv4) = range.parse() {
+            return Ok(AnyRange::V4(ipv4));
+        }
+        range
+            .parse()
+            .map(AnyRange::V6)
+            .map_err(|_| de::Error::custom(format!("invalid address, CIDR block or range: {}", range)))
+    }
+}
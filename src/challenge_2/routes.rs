@@ -1,60 +1,240 @@
-use crate::challenge_2::structs::{Ipv4RouterDecryptQuery, Ipv4RouterQuery, Ipv6RouterDecryptQuery, Ipv6RouterQuery};
-use axum::extract::Query;
-use axum::response::IntoResponse;
-use std::net::{Ipv4Addr, Ipv6Addr};
-use std::ops::BitXor;
+use crate::challenge_2::ciphers::CipherMode;
+use crate::challenge_2::families::normalize_pair;
+use crate::challenge_2::problems::{Problem, ProblemQuery};
+use crate::challenge_2::ranges::{Address, AddressRange, AnyRange, MAX_RANGE_SIZE};
+use crate::challenge_2::structs::{BatchItem, BatchResult, RouterDecryptQuery, RouterQuery};
+use crate::headers::wants_json;
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures::{stream, StreamExt};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::net::IpAddr;
+
+/// How many ciphered addresses of a range are sent at once.
+const RANGE_CHUNK_SIZE: usize = 256;
+/// Most items handled by a single batch request.
+const MAX_BATCH_SIZE: usize = 1000;
+/// Header set on the responses to ranges cut after `MAX_RANGE_SIZE` addresses.
+const RANGE_TRUNCATED_HEADER: &str = "x-range-truncated";
+
+/// Ciphers every address of a range. A single address is answered with its result alone as plain text, while ranges
+/// are streamed one result per line. Clients accepting JSON get an array of addresses along with their result instead.
+/// Addresses without a result, such as the ones no key turns into the wanted address, get an empty line or a null.
+/// Ranges too large to be ciphered entirely are answered with the results of their first addresses, flagged by the
+/// `X-Range-Truncated` header.
+fn cipher_range<A: Address, R: Display>(
+    headers: &HeaderMap,
+    range: AddressRange<A>,
+    cipher: impl Fn(A) -> Option<R> + Send + 'static,
+) -> Response {
+    let json = wants_json(headers);
+    if range.is_single() && !json {
+        return match range.addresses().find_map(cipher) {
+            Some(result) => result.to_string().into_response(),
+            None => {
+                tracing::info!("Error: no result for the given addresses");
+                StatusCode::UNPROCESSABLE_ENTITY.into_response()
+            }
+        };
+    }
+    let truncated = range.is_capped();
+    if truncated {
+        tracing::info!("Range larger than {} addresses, only ciphering the first ones", MAX_RANGE_SIZE);
+    }
+    let lines = range.addresses().enumerate().map(move |(i, address)| {
+        let result = cipher(address).map(|result| result.to_string());
+        if json {
+            let separator = if i == 0 { "[" } else { "," };
+            format!("{}{}", separator, json!({ "address": address.to_string(), "result": result }))
+        } else {
+            format!("{}\n", result.unwrap_or_default())
+        }
+    });
+    let end = json.then(|| "]".to_string());
+    let chunks = stream::iter(lines.chain(end))
+        .chunks(RANGE_CHUNK_SIZE)
+        .map(|lines| Ok::<_, Infallible>(lines.concat()));
+    let content_type = if json { "application/json" } else { "text/plain; charset=utf-8" };
+    let mut response = ([(header::CONTENT_TYPE, content_type)], Body::from_stream(chunks)).into_response();
+    if truncated {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(RANGE_TRUNCATED_HEADER), HeaderValue::from_static("true"));
+    }
+    response
+}
+
+/// Modes used by default, as in the original challenge.
+const IPV4_DEFAULT_MODE: CipherMode = CipherMode::Add;
+const IPV6_DEFAULT_MODE: CipherMode = CipherMode::Xor;
+
+/// Rejects the modes where the key can't be found from an address and its encrypted form.
+fn key_recovering_mode(mode: CipherMode) -> Result<CipherMode, Problem> {
+    if mode.cipher().recovers_keys() {
+        Ok(mode)
+    } else {
+        let received = format!("{:?}", mode).to_lowercase();
+        Err(Problem::invalid_parameter::<RouterDecryptQuery>(
+            Some("mode"),
+            Some(received),
+            "keys can't be recovered in this mode",
+        ))
+    }
+}
+
+/// Encrypts every address of `from` with the cipher of its family, in the given mode or in the default one.
+fn encrypt_range(query: RouterQuery, headers: &HeaderMap) -> Result<Response, Problem> {
+    let (mode, format) = (query.mode, query.format);
+    match query.from.normalize_with(query.key) {
+        (AnyRange::V4(from), IpAddr::V4(key)) => {
+            let mode = mode.unwrap_or(IPV4_DEFAULT_MODE);
+            Ok(cipher_range(headers, from, move |from| Some(format.format(mode.encrypt(from, key).into()))))
+        }
+        (AnyRange::V6(from), IpAddr::V6(key)) => {
+            let mode = mode.unwrap_or(IPV6_DEFAULT_MODE);
+            Ok(cipher_range(headers, from, move |from| Some(format.format(mode.encrypt(from, key).into()))))
+        }
+        (_, key) => Err(Problem::invalid_parameter::<RouterQuery>(
+            Some("key"),
+            Some(key.to_string()),
+            "from and key are not of the same address family",
+        )),
+    }
+}
+
+/// Finds the key turning every address of `from` into `to`, with the cipher of their family.
+fn recover_keys(query: RouterDecryptQuery, headers: &HeaderMap) -> Result<Response, Problem> {
+    let (mode, format) = (query.mode, query.format);
+    match query.from.normalize_with(query.to) {
+        (AnyRange::V4(from), IpAddr::V4(to)) => {
+            let mode = key_recovering_mode(mode.unwrap_or(IPV4_DEFAULT_MODE))?;
+            Ok(cipher_range(headers, from, move |from| Some(format.format(mode.recover_key(from, to)?.into()))))
+        }
+        (AnyRange::V6(from), IpAddr::V6(to)) => {
+            let mode = key_recovering_mode(mode.unwrap_or(IPV6_DEFAULT_MODE))?;
+            Ok(cipher_range(headers, from, move |from| Some(format.format(mode.recover_key(from, to)?.into()))))
+        }
+        (_, to) => Err(Problem::invalid_parameter::<RouterDecryptQuery>(
+            Some("to"),
+            Some(to.to_string()),
+            "from and to are not of the same address family",
+        )),
+    }
+}
 
 /// Implements task 1 for challenge 2.
-pub(crate) async fn ipv4_router(query_params: Query<Ipv4RouterQuery>) -> impl IntoResponse {
-    let from_octets = query_params.0.from.octets();
-    let key_octets = query_params.0.key.octets();
-    let encrypted_octets: [u8; 4] = from_octets
-        .into_iter()
-        .zip(key_octets)
-        .map(|(from_octet, key_octet)| { from_octet.overflowing_add(key_octet).0 })
-        .collect::<Vec<u8>>()
-        .try_into()
-        .unwrap(); // This unwrap is safe due to the type of encrypted_octets depending on the type of from/key octets
-    Ipv4Addr::from(encrypted_octets).to_string()
+/// `from` can also be a CIDR block or a range, in which case every address of it is encrypted.
+/// The `mode` query parameter picks another cipher than the default addition, and `format` how results are written.
+/// IPv6 addresses are accepted too, getting the same cipher as on the IPv6 route, while IPv4 addresses embedded in
+/// IPv6 ones get the IPv4 cipher on both routes, unless the other address is a plain IPv6 one.
+pub(crate) async fn ipv4_router(query_params: ProblemQuery<RouterQuery>, headers: HeaderMap) -> Result<Response, Problem> {
+    encrypt_range(query_params.0, &headers)
 }
 
 /// Implements task 2 for challenge 2.
-pub(crate) async fn ipv4_router_decrypt(query_params: Query<Ipv4RouterDecryptQuery>) -> impl IntoResponse {
-    let from_octets = query_params.0.from.octets();
-    let to_octets = query_params.0.to.octets();
-    let decrypted_octets: [u8; 4] = from_octets
-        .into_iter()
-        .zip(to_octets)
-        .map(|(from_octet, to_octet)| { to_octet.overflowing_sub(from_octet).0 })
-        .collect::<Vec<u8>>()
-        .try_into()
-        .unwrap(); // This unwrap is safe due to the type of encrypted_octets depending on the type of from/to octets
-    Ipv4Addr::from(decrypted_octets).to_string()
+/// `from` can also be a CIDR block or a range, in which case the key is found for every address of it.
+pub(crate) async fn ipv4_router_decrypt(query_params: ProblemQuery<RouterDecryptQuery>, headers: HeaderMap) -> Result<Response, Problem> {
+    recover_keys(query_params.0, &headers)
 }
 
 /// Implements task 3 for challenge 2.
-pub(crate) async fn ipv6_router(query_params: Query<Ipv6RouterQuery>) -> impl IntoResponse {
-    let from_octets = query_params.0.from.octets();
-    let key_octets = query_params.0.key.octets();
-    let decrypted_octets: [u8; 16] = from_octets
-        .into_iter()
-        .zip(key_octets)
-        .map(|(from_octet, key_octet)| { from_octet.bitxor(key_octet) })
-        .collect::<Vec<u8>>()
-        .try_into()
-        .unwrap(); // This unwrap is safe due to the type of encrypted_octets depending on the type of from/key octets
-    Ipv6Addr::from(decrypted_octets).to_string()
-}
-
-pub(crate) async fn ipv6_router_decrypt(query_params: Query<Ipv6RouterDecryptQuery>) -> impl IntoResponse {
-    let from_octets = query_params.0.from.octets();
-    let to_octets = query_params.0.to.octets();
-    let decrypted_octets: [u8; 16] = from_octets
+pub(crate) async fn ipv6_router(query_params: ProblemQuery<RouterQuery>, headers: HeaderMap) -> Result<Response, Problem> {
+    encrypt_range(query_params.0, &headers)
+}
+
+pub(crate) async fn ipv6_router_decrypt(query_params: ProblemQuery<RouterDecryptQuery>, headers: HeaderMap) -> Result<Response, Problem> {
+    recover_keys(query_params.0, &headers)
+}
+
+/// Ciphers a single item of a batch, with the same logic as the endpoints of its address family.
+fn cipher_item(item: Value) -> Result<String, String> {
+    let item: BatchItem = serde_json::from_value(item).map_err(|e| format!("invalid item: {}", e))?;
+    let parse = |field: &str, address: &str| {
+        address.parse::<IpAddr>().map_err(|_| format!("invalid {} address: {}", field, address))
+    };
+    let from = parse("from", &item.from)?;
+    let (field, other, decrypt) = match (item.key, item.to) {
+        (Some(key), None) => ("key", key, false),
+        (None, Some(to)) => ("to", to, true),
+        _ => return Err("exactly one of key and to is expected".to_string()),
+    };
+    let mode = |default: CipherMode| item.mode.unwrap_or(default);
+    let other = parse(field, &other)?;
+    let result = match normalize_pair(from, other) {
+        (IpAddr::V4(from), IpAddr::V4(to)) if decrypt => mode(IPV4_DEFAULT_MODE).recover_key(from, to).map(IpAddr::V4),
+        (IpAddr::V4(from), IpAddr::V4(key)) => Some(IpAddr::V4(mode(IPV4_DEFAULT_MODE).encrypt(from, key))),
+        (IpAddr::V6(from), IpAddr::V6(to)) if decrypt => mode(IPV6_DEFAULT_MODE).recover_key(from, to).map(IpAddr::V6),
+        (IpAddr::V6(from), IpAddr::V6(key)) => Some(IpAddr::V6(mode(IPV6_DEFAULT_MODE).encrypt(from, key))),
+        _ => return Err(format!("from and {} are not of the same address family", field)),
+    };
+    result.map(|result| result.to_string()).ok_or_else(|| "no key turns from into to in this mode".to_string())
+}
+
+/// Encrypts or decrypts many IPv4 and IPv6 addresses at once. Each item either succeeds with its `result` or fails
+/// with an `error`, without affecting the other items.
+pub(crate) async fn batch_router(Json(items): Json<Vec<Value>>) -> Result<Json<Vec<BatchResult>>, StatusCode> {
+    if items.len() > MAX_BATCH_SIZE {
+        tracing::info!("Error: batch larger than {} items", MAX_BATCH_SIZE);
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let results = items
         .into_iter()
-        .zip(to_octets)
-        .map(|(from_octet, to_octet)| { from_octet.bitxor(to_octet) })
-        .collect::<Vec<u8>>()
-        .try_into()
-        .unwrap(); // This unwrap is safe due to the type of encrypted_octets depending on the type of from/key octets
-    Ipv6Addr::from(decrypted_octets).to_string()
-}
\ No newline at end of file
+        .map(|item| match cipher_item(item) {
+            Ok(result) => BatchResult::Ok { result },
+            Err(error) => BatchResult::Err { error },
+        })
+        .collect();
+    Ok(Json(results))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body(result: Result<Response, Problem>) -> String {
+        let Ok(response) = result else { panic!("the addresses were rejected") };
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    async fn encrypt(query: &str) -> String {
+        body(encrypt_range(serde_urlencoded::from_str(query).unwrap(), &HeaderMap::new())).await
+    }
+
+    async fn recover(query: &str) -> String {
+        body(recover_keys(serde_urlencoded::from_str(query).unwrap(), &HeaderMap::new())).await
+    }
+
+    #[tokio::test]
+    async fn mapped_key_gets_the_ipv4_cipher() {
+        assert_eq!(encrypt("from=1.2.3.4&key=::ffff:0.0.0.1").await, "1.2.3.5");
+        assert_eq!(cipher_item(json!({ "from": "1.2.3.4", "key": "::ffff:0.0.0.1" })), Ok("1.2.3.5".to_string()));
+    }
+
+    #[tokio::test]
+    async fn mapped_to_gets_the_ipv4_cipher() {
+        assert_eq!(recover("from=1.2.3.4&to=::ffff:1.2.3.5").await, "0.0.0.1");
+        assert_eq!(cipher_item(json!({ "from": "1.2.3.4", "to": "::ffff:1.2.3.5" })), Ok("0.0.0.1".to_string()));
+    }
+
+    #[tokio::test]
+    async fn mapped_pair_gets_the_ipv4_cipher() {
+        assert_eq!(encrypt("from=::ffff:10.0.0.1&key=::ffff:1.2.3.4").await, "11.2.3.5");
+        assert_eq!(encrypt("from=::ffff:10.0.0.1&key=::ffff:1.2.3.4&format=mapped").await, "::ffff:11.2.3.5");
+        assert_eq!(recover("from=::ffff:10.0.0.1&to=::ffff:11.2.3.5").await, "1.2.3.4");
+        assert_eq!(
+            cipher_item(json!({ "from": "::ffff:10.0.0.1", "key": "::ffff:1.2.3.4" })),
+            Ok("11.2.3.5".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn mapped_address_along_with_plain_ipv6_keeps_the_ipv6_cipher() {
+        assert_eq!(encrypt("from=::ffff:10.0.0.1&key=ffff::").await, "ffff::ffff:a00:1");
+        assert_eq!(cipher_item(json!({ "from": "::ffff:10.0.0.1", "key": "ffff::" })), Ok("ffff::ffff:a00:1".to_string()));
+    }
+}
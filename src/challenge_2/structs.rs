@@ -1,26 +1,79 @@
-use serde::Deserialize;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use crate::challenge_2::ciphers::CipherMode;
+use crate::challenge_2::families::{deserialize_address, OutputFormat};
+use crate::challenge_2::problems::QueryParameters;
+use crate::challenge_2::ranges::AnyRange;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 
+const EXPECTED_RANGE: &str = "an IPv4 or IPv6 address, a CIDR block such as 10.0.0.0/24 or a range such as 10.0.0.1-10.0.0.9";
+const EXPECTED_ADDRESS: &str = "an IPv4 or IPv6 address of the same family as from";
+const EXPECTED_MODE: &str = "one of add, xor, rotate or feistel";
+const EXPECTED_FORMAT: &str = "one of canonical, expanded or mapped";
+
+/// Query of the routes encrypting addresses. Addresses of either family are accepted on every route, the family of
+/// `from` picking the cipher, and `key` must be of the same family. IPv4 addresses embedded in IPv6 ones count as IPv4
+/// addresses, unless the other address is a plain IPv6 one.
 #[derive(Deserialize)]
-pub(crate) struct Ipv4RouterQuery {
-    pub from: Ipv4Addr,
-    pub key: Ipv4Addr,
+pub(crate) struct RouterQuery {
+    pub from: AnyRange,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub key: IpAddr,
+    pub mode: Option<CipherMode>,
+    #[serde(default)]
+    pub format: OutputFormat,
 }
 
-#[derive(Deserialize)]
-pub(crate) struct Ipv4RouterDecryptQuery {
-    pub from: Ipv4Addr,
-    pub to: Ipv4Addr,
+impl QueryParameters for RouterQuery {
+    fn expected(parameter: &str) -> Option<&'static str> {
+        match parameter {
+            "from" => Some(EXPECTED_RANGE),
+            "key" => Some(EXPECTED_ADDRESS),
+            "mode" => Some(EXPECTED_MODE),
+            "format" => Some(EXPECTED_FORMAT),
+            _ => None,
+        }
+    }
 }
 
+/// Query of the routes finding the keys turning addresses into `to`, which must be of the same family as `from`.
 #[derive(Deserialize)]
-pub(crate) struct Ipv6RouterQuery {
-    pub from: Ipv6Addr,
-    pub key: Ipv6Addr,
+pub(crate) struct RouterDecryptQuery {
+    pub from: AnyRange,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub to: IpAddr,
+    pub mode: Option<CipherMode>,
+    #[serde(default)]
+    pub format: OutputFormat,
 }
 
-#[derive(Deserialize)]
-pub(crate) struct Ipv6RouterDecryptQuery {
-    pub from: Ipv6Addr,
-    pub to: Ipv6Addr,
-}
\ No newline at end of file
+impl QueryParameters for RouterDecryptQuery {
+    fn expected(parameter: &str) -> Option<&'static str> {
+        match parameter {
+            "from" => Some(EXPECTED_RANGE),
+            "to" => Some(EXPECTED_ADDRESS),
+            // Keys can't be recovered from the encrypted addresses of the other modes
+            "mode" => Some("one of add, xor or rotate"),
+            "format" => Some(EXPECTED_FORMAT),
+            _ => None,
+        }
+    }
+}
+
+/// An address to encrypt with `key`, or a pair of addresses to find the key of with `to`, as sent to the batch
+/// endpoint. Addresses are kept as text, so that a malformed one only fails its own item. The cipher mode defaults to
+/// the one of the address family, as for the other endpoints.
+#[derive(Deserialize, Debug)]
+pub(crate) struct BatchItem {
+    pub from: String,
+    pub key: Option<String>,
+    pub to: Option<String>,
+    pub mode: Option<CipherMode>,
+}
+
+/// Outcome of a single item of a batch, in the same position as the item.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub(crate) enum BatchResult {
+    Ok { result: String },
+    Err { error: String },
+}
@@ -1,7 +1,8 @@
-use axum::extract::Path;
+use axum::extract::{Multipart, Path};
 use axum::http::StatusCode;
 use axum::response::{Html, IntoResponse};
 use html_escape::encode_text;
+use toml::Value;
 
 const COLORS: [&str; 3] = ["red", "blue", "purple"];
 
@@ -14,7 +15,7 @@ pub(crate) async fn star() -> Html<&'static str> {
 }
 
 pub(crate) async fn get_present(Path(color): Path<String>) -> Result<Html<String>, StatusCode> {
-    let color = escape_quotes(&*encode_text(&color));
+    let color = escape_quotes(&encode_text(&color));
     if let Some(current_index) = COLORS.iter().position(|&c| c == color) {
         let next_color = COLORS[(current_index + 1) % COLORS.len()];
         let next_color = encode_text(next_color);
@@ -30,8 +31,8 @@ pub(crate) async fn get_present(Path(color): Path<String>) -> Result<Html<String
 }
 
 pub(crate) async fn get_ornament(Path((state, n)): Path<(String, String)>) -> impl IntoResponse {
-    let state = escape_quotes(&*encode_text(&state));
-    let n = escape_quotes(&*encode_text(&n));
+    let state = escape_quotes(&encode_text(&state));
+    let n = escape_quotes(&encode_text(&n));
     let next_state = match state.to_string().as_str() {
         "on" => "off",
         "off" => "on",
@@ -54,3 +55,76 @@ pub(crate) async fn get_ornament(Path((state, n)): Path<(String, String)>) -> im
     Html(response).into_response()
 }
 
+/// Turns a package checksum into a sprinkle: the first 6 hex digits make up the color, the following two bytes make
+/// up the top and left offsets. Returns None if the checksum isn't valid hex or is too short.
+fn sprinkle_from_checksum(checksum: &str) -> Option<String> {
+    if checksum.len() < 10 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let color = &checksum[0..6];
+    let top = u8::from_str_radix(&checksum[6..8], 16).ok()?;
+    let left = u8::from_str_radix(&checksum[8..10], 16).ok()?;
+    Some(format!(
+        r#"<div class="sprinkle" style="background-color:#{color};top:{top}px;left:{left}px;"></div>"#,
+        color = escape_quotes(&encode_text(color)),
+        top = top,
+        left = left,
+    ))
+}
+
+pub(crate) async fn lockfile(mut multipart: Multipart) -> impl IntoResponse {
+    let mut lockfile = None;
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.name() == Some("lockfile") => match field.text().await {
+                Ok(text) => {
+                    lockfile = Some(text);
+                    break;
+                }
+                Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+            },
+            Ok(Some(_)) => continue,
+            Ok(None) => break,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        }
+    }
+    let lockfile = match lockfile {
+        Some(lockfile) => lockfile,
+        None => {
+            tracing::info!("Missing lockfile field");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+    tracing::info!("Lockfile raw input: {:#?}", lockfile);
+    let value = match toml::from_str::<Value>(&lockfile) {
+        Ok(value) => value,
+        Err(_) => {
+            tracing::info!("Invalid lockfile");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+    let packages = match value.get("package") {
+        Some(Value::Array(packages)) => packages,
+        _ => {
+            tracing::info!("Missing package entries");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+
+    let mut sprinkles = Vec::new();
+    for package in packages {
+        let checksum = match package.get("checksum") {
+            Some(Value::String(checksum)) => checksum,
+            _ => continue,
+        };
+        match sprinkle_from_checksum(checksum) {
+            Some(sprinkle) => sprinkles.push(sprinkle),
+            None => {
+                tracing::info!("Invalid checksum {}", checksum);
+                return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+            }
+        }
+    }
+
+    Html(sprinkles.join("\n")).into_response()
+}
@@ -8,7 +8,7 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 
 const LITER_TO_GALLON: f32 = 0.264172;
-const LITRE_TO_UK_PINT: f32 = 1.7597539864;
+const LITRE_TO_UK_PINT: f32 = 1.759_754;
 
 // https://stackoverflow.com/questions/69834142/how-to-only-allow-one-field-or-the-other-with-serde
 #[derive(Debug, Serialize, Deserialize)]
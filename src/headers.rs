@@ -0,0 +1,9 @@
+use axum::http::{header, HeaderMap};
+
+/// Whether the client asked for a JSON response through the Accept header.
+pub(crate) fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"))
+}
@@ -1,10 +1,11 @@
 use axum::routing::{delete, post, put};
 use axum::{routing::get, Router};
 use leaky_bucket::RateLimiter;
+use shuttle_runtime::SecretStore;
 use sqlx::PgPool;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 use tower_http::services::ServeDir;
 
 #[path = "challenge_-1/mod.rs"]
@@ -16,36 +17,86 @@ mod challenge_12;
 mod challenge_16;
 mod challenge_19;
 mod challenge_23;
+mod headers;
 
-use crate::challenge_12::routes::{board, place, reset_board};
-use crate::challenge_12::structs::Grid;
-use crate::challenge_16::routes::{unwrap, wrap};
-use crate::challenge_19::routes::{add_quote, delete_quote, get_quote, reset_quotes, update_quote};
-use crate::challenge_2::routes::{ipv4_router_decrypt, ipv6_router, ipv6_router_decrypt};
-use crate::challenge_23::routes::{get_ornament, get_present, star};
+use crate::challenge_12::board::{GlobalBoard, SharedGlobalBoard};
+use crate::challenge_12::rooms::{evict_idle_rooms, GameRoom, GameRooms};
+use crate::challenge_12::persistence::{stored_games, StoredGame, GLOBAL_BOARD_ID};
+use crate::challenge_12::routes::{board, board_events, board_image, board_socket, create_game, finished_game_image, game_board, game_events, game_history, game_image, game_replay, game_socket, get_leaderboard, get_player_stats, hint, history, join_game, list_finished_games, load_board, load_game, place, place_in_game, pop, pop_in_game, random_board, replay, replay_finished_game, reset_board, reset_game, undo, undo_game};
+use crate::challenge_16::routes::{decode_santa_token, unwrap, wrap};
+use crate::challenge_19::routes::{add_quote, delete_quote, export_quotes, get_quote, get_quote_history, get_quote_revision, import_quotes, list_quotes, reset_quotes, restore_quote, rollback_quote, search_quotes, update_quote};
+use crate::challenge_19::structs::PageTokens;
+use crate::challenge_19::tasks::purge_deleted_quotes;
+use crate::challenge_2::routes::{batch_router, ipv4_router_decrypt, ipv6_router, ipv6_router_decrypt};
+use crate::challenge_23::routes::{get_ornament, get_present, lockfile, star};
 use crate::challenge_5::routes::manifest;
 use crate::challenge_9::routes::{milk, refill};
 use challenge_2::routes::ipv4_router;
 use challenge_neg1::routes::{hello_world, seek};
 
+/// How long a game room can stay idle before being evicted, unless set by the `GAME_ROOM_TTL_SECS` secret.
+const GAME_ROOM_TTL: Duration = Duration::from_secs(30 * 60);
+/// How often idle game rooms are looked for.
+const GAME_ROOM_EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+/// How long the continuation tokens of the quotes list endpoint stay valid, unless set by the `PAGE_TOKEN_TTL_SECS`
+/// secret.
+const PAGE_TOKEN_TTL: Duration = Duration::from_secs(60 * 60);
+/// How long soft deleted quotes are kept around before being purged for good, unless set by the
+/// `QUOTE_RETENTION_SECS` secret.
+const QUOTE_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// How often the purge of soft deleted quotes runs.
+const QUOTE_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Settings read from the Shuttle secrets, as numbers of seconds, falling back to their defaults when missing.
+#[derive(Debug)]
+struct Settings {
+    page_token_ttl: Duration,
+    quote_retention: Duration,
+    game_room_ttl: Duration,
+}
+
+impl Settings {
+    fn from_secrets(secrets: &SecretStore) -> Self {
+        Self {
+            page_token_ttl: duration_secret(secrets, "PAGE_TOKEN_TTL_SECS", PAGE_TOKEN_TTL),
+            quote_retention: duration_secret(secrets, "QUOTE_RETENTION_SECS", QUOTE_RETENTION),
+            game_room_ttl: duration_secret(secrets, "GAME_ROOM_TTL_SECS", GAME_ROOM_TTL),
+        }
+    }
+}
+
+fn duration_secret(secrets: &SecretStore, name: &str, default: Duration) -> Duration {
+    match secrets.get(name).map(|secs| secs.trim().parse()) {
+        Some(Ok(secs)) => Duration::from_secs(secs),
+        Some(Err(e)) => {
+            tracing::error!("Invalid {} secret, keeping the default of {:?}: {:?}", name, default, e);
+            default
+        }
+        None => default,
+    }
+}
+
 #[derive(Debug)]
 struct AppState {
     rate_limiter: RateLimiter,
-    board: Grid,
+    board: SharedGlobalBoard,
+    rooms: Arc<GameRooms>,
     pool: PgPool,
+    page_tokens: PageTokens,
 }
 
-
 impl AppState {
-    fn new(pool: PgPool) -> Self {
+    fn new(pool: PgPool, settings: &Settings) -> Self {
         Self {
             rate_limiter: RateLimiter::builder()
                 .max(5)
                 .initial(5)
                 .interval(Duration::from_millis(1000))
                 .build(),
-            board: Default::default(),
-            pool: pool,
+            board: Arc::new(Mutex::new(GlobalBoard::new())),
+            rooms: Arc::new(GameRooms::new(settings.game_room_ttl)),
+            pool,
+            page_tokens: PageTokens::new(settings.page_token_ttl),
         }
     }
     fn reset_bucket(&mut self) {
@@ -56,8 +107,23 @@ impl AppState {
             .build()
     }
 
-    fn reset_board(&mut self) {
-        self.board = Grid::default();
+    /// Restores the board and the game rooms stored by the previous runs or by the other instances.
+    async fn restore_games(&mut self) {
+        let games = match stored_games(&self.pool).await {
+            Ok(games) => games,
+            Err(e) => {
+                tracing::error!("Error while restoring games: {:#?}", e);
+                return;
+            }
+        };
+        tracing::info!("Restoring {} stored games", games.len());
+        for (id, snapshot, version) in games {
+            if id == GLOBAL_BOARD_ID {
+                self.board.lock().await.restore(snapshot, version);
+            } else {
+                self.rooms.restore(id, GameRoom::from_snapshot(snapshot, version));
+            }
+        }
     }
 }
 
@@ -66,13 +132,22 @@ type SharedState = Arc<RwLock<AppState>>;
 #[shuttle_runtime::main]
 async fn main(
     #[shuttle_shared_db::Postgres] pool: PgPool,
+    #[shuttle_runtime::Secrets] secrets: SecretStore,
 ) -> shuttle_axum::ShuttleAxum {
+    let settings = Settings::from_secrets(&secrets);
+    tracing::info!("Settings: {:?}", settings);
     sqlx::migrate!()
         .run(&pool)
         .await
         .expect("Failed to run migrations");
 
-    let shared_state = SharedState::new(RwLock::new(AppState::new(pool)));
+    tokio::spawn(purge_deleted_quotes(pool.clone(), settings.quote_retention, QUOTE_PURGE_INTERVAL));
+
+    let mut state = AppState::new(pool, &settings);
+    state.restore_games().await;
+    tokio::spawn(evict_idle_rooms(state.rooms.clone(), state.pool.clone(), GAME_ROOM_EVICTION_INTERVAL));
+
+    let shared_state = SharedState::new(RwLock::new(state));
     let router = Router::new()
 
         .route("/", get(hello_world))
@@ -81,22 +156,61 @@ async fn main(
         .route("/2/key", get(ipv4_router_decrypt))
         .route("/2/v6/dest", get(ipv6_router))
         .route("/2/v6/key", get(ipv6_router_decrypt))
+        .route("/2/batch", post(batch_router))
         .route("/5/manifest", post(manifest))
         .route("/9/milk", post(milk))
         .route("/9/refill", post(refill))
         .route("/12/board", get(board))
         .route("/12/reset", post(reset_board))
         .route("/12/place/:team/:column", post(place))
+        .route("/12/pop/:team/:column", post(pop))
+        .route("/12/random-board", get(random_board))
+        .route("/12/load", post(load_board))
+        .route("/12/history", get(history))
+        .route("/12/replay", get(replay))
+        .route("/12/undo", post(undo))
+        .route("/12/image/:format", get(board_image))
+        .route("/12/board/events", get(board_events))
+        .route("/12/board/ws", get(board_socket))
+        .route("/12/games", post(create_game))
+        .route("/12/games/:id/board", get(game_board))
+        .route("/12/games/:id/reset", post(reset_game))
+        .route("/12/games/:id/join/:team", post(join_game))
+        .route("/12/games/:id/place/:team/:column", post(place_in_game))
+        .route("/12/games/:id/pop/:team/:column", post(pop_in_game))
+        .route("/12/games/:id/load", post(load_game))
+        .route("/12/games/:id/history", get(game_history))
+        .route("/12/games/:id/replay", get(game_replay))
+        .route("/12/games/:id/undo", post(undo_game))
+        .route("/12/games/:id/image/:format", get(game_image))
+        .route("/12/finished", get(list_finished_games))
+        .route("/12/finished/:id/replay", get(replay_finished_game))
+        .route("/12/finished/:id/image/:format", get(finished_game_image))
+        .route("/12/leaderboard", get(get_leaderboard))
+        .route("/12/players/:name", get(get_player_stats))
+        .route("/12/games/:id/hint", get(hint))
+        .route("/12/games/:id/events", get(game_events))
+        .route("/12/games/:id/ws", get(game_socket))
         .route("/16/wrap", post(wrap))
         .route("/16/unwrap", get(unwrap))
+        .route("/16/decode", post(decode_santa_token))
         .route("/19/reset", post(reset_quotes))
         .route("/19/cite/:id", get(get_quote))
         .route("/19/remove/:id", delete(delete_quote))
         .route("/19/undo/:id", put(update_quote))
         .route("/19/draft", post(add_quote))
+        .route("/19/restore/:id", post(restore_quote))
+        .route("/19/search", get(search_quotes))
+        .route("/19/export", get(export_quotes))
+        .route("/19/import", post(import_quotes))
+        .route("/19/list", get(list_quotes))
+        .route("/19/history/:id", get(get_quote_history))
+        .route("/19/history/:id/:version", get(get_quote_revision))
+        .route("/19/rollback/:id/:version", post(rollback_quote))
         .route("/23/star", get(star))
         .route("/23/present/:color", get(get_present))
         .route("/23/ornament/:state/:n", get(get_ornament))
+        .route("/23/lockfile", post(lockfile))
         .nest_service("/assets", ServeDir::new("assets"))
         .with_state(shared_state);
 